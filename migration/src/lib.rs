@@ -4,6 +4,22 @@ mod m20221221_000001_user;
 mod m20221222_000002_session;
 mod m20221222_000003_room;
 mod m20221222_000004_member;
+mod m20221223_000005_message;
+mod m20221224_000006_message_revision;
+mod m20221225_000007_message_deleted;
+mod m20221226_000008_user_availability;
+mod m20221227_000009_member_last_read;
+mod m20221228_000010_reaction;
+mod m20221229_000011_message_thread;
+mod m20221230_000012_attachment;
+mod m20221231_000013_attachment_image;
+mod m20230101_000014_message_fts;
+mod m20230102_000015_mention;
+mod m20230103_000016_member_role;
+mod m20230104_000017_room_invite;
+mod m20230105_000018_room_ban;
+mod m20230106_000019_room_settings;
+mod m20230107_000020_dm;
 
 pub struct Migrator;
 
@@ -15,6 +31,22 @@ impl MigratorTrait for Migrator {
       Box::new(m20221222_000002_session::Migration),
       Box::new(m20221222_000003_room::Migration),
       Box::new(m20221222_000004_member::Migration),
+      Box::new(m20221223_000005_message::Migration),
+      Box::new(m20221224_000006_message_revision::Migration),
+      Box::new(m20221225_000007_message_deleted::Migration),
+      Box::new(m20221226_000008_user_availability::Migration),
+      Box::new(m20221227_000009_member_last_read::Migration),
+      Box::new(m20221228_000010_reaction::Migration),
+      Box::new(m20221229_000011_message_thread::Migration),
+      Box::new(m20221230_000012_attachment::Migration),
+      Box::new(m20221231_000013_attachment_image::Migration),
+      Box::new(m20230101_000014_message_fts::Migration),
+      Box::new(m20230102_000015_mention::Migration),
+      Box::new(m20230103_000016_member_role::Migration),
+      Box::new(m20230104_000017_room_invite::Migration),
+      Box::new(m20230105_000018_room_ban::Migration),
+      Box::new(m20230106_000019_room_settings::Migration),
+      Box::new(m20230107_000020_dm::Migration),
     ]
   }
 }
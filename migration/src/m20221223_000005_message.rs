@@ -0,0 +1,58 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+  async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .create_table(
+        Table::create()
+          .table(Message::Table)
+          .if_not_exists()
+          .col(
+            ColumnDef::new(Message::Uuid)
+              .uuid()
+              .not_null()
+              .primary_key(),
+          )
+          .col(ColumnDef::new(Message::Sender).integer().not_null())
+          .col(ColumnDef::new(Message::Room).integer().not_null())
+          .col(ColumnDef::new(Message::Data).text().not_null())
+          .col(ColumnDef::new(Message::Sent).timestamp().not_null())
+          .col(ColumnDef::new(Message::Modified).boolean().not_null())
+          .to_owned(),
+      )
+      .await?;
+
+    manager
+      .create_index(
+        Index::create()
+          .name("idx-message-room-sent")
+          .table(Message::Table)
+          .col(Message::Room)
+          .col(Message::Sent)
+          .to_owned(),
+      )
+      .await
+  }
+
+  async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .drop_table(Table::drop().table(Message::Table).to_owned())
+      .await
+  }
+}
+
+/// Learn more at https://docs.rs/sea-query#iden
+#[derive(Iden)]
+enum Message {
+  Table,
+  Uuid,
+  Sender,
+  Room,
+  Data,
+  Sent,
+  Modified,
+}
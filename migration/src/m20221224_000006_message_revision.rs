@@ -0,0 +1,80 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+  async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .alter_table(
+        Table::alter()
+          .table(Message::Table)
+          .add_column(ColumnDef::new(Message::Edited).timestamp().null())
+          .to_owned(),
+      )
+      .await?;
+
+    manager
+      .create_table(
+        Table::create()
+          .table(MessageRevision::Table)
+          .if_not_exists()
+          .col(
+            ColumnDef::new(MessageRevision::Id)
+              .integer()
+              .not_null()
+              .auto_increment()
+              .primary_key(),
+          )
+          .col(ColumnDef::new(MessageRevision::Message).uuid().not_null())
+          .col(ColumnDef::new(MessageRevision::Data).text().not_null())
+          .col(ColumnDef::new(MessageRevision::Written).timestamp().not_null())
+          .col(ColumnDef::new(MessageRevision::Replaced).timestamp().not_null())
+          .to_owned(),
+      )
+      .await?;
+
+    manager
+      .create_index(
+        Index::create()
+          .name("idx-message_revision-message")
+          .table(MessageRevision::Table)
+          .col(MessageRevision::Message)
+          .to_owned(),
+      )
+      .await
+  }
+
+  async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .drop_table(Table::drop().table(MessageRevision::Table).to_owned())
+      .await?;
+
+    manager
+      .alter_table(
+        Table::alter()
+          .table(Message::Table)
+          .drop_column(Message::Edited)
+          .to_owned(),
+      )
+      .await
+  }
+}
+
+/// Learn more at https://docs.rs/sea-query#iden
+#[derive(Iden)]
+enum Message {
+  Table,
+  Edited,
+}
+
+#[derive(Iden)]
+enum MessageRevision {
+  Table,
+  Id,
+  Message,
+  Data,
+  Written,
+  Replaced,
+}
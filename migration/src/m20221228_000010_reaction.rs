@@ -0,0 +1,44 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+  async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .create_table(
+        Table::create()
+          .table(Reaction::Table)
+          .if_not_exists()
+          .col(ColumnDef::new(Reaction::Message).uuid().not_null())
+          .col(ColumnDef::new(Reaction::User).integer().not_null())
+          .col(ColumnDef::new(Reaction::Emoji).string().not_null())
+          .col(ColumnDef::new(Reaction::Reacted).timestamp().not_null())
+          .primary_key(
+            Index::create()
+              .col(Reaction::Message)
+              .col(Reaction::User)
+              .col(Reaction::Emoji),
+          )
+          .to_owned(),
+      )
+      .await
+  }
+
+  async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .drop_table(Table::drop().table(Reaction::Table).to_owned())
+      .await
+  }
+}
+
+/// Learn more at https://docs.rs/sea-query#iden
+#[derive(Iden)]
+enum Reaction {
+  Table,
+  Message,
+  User,
+  Emoji,
+  Reacted,
+}
@@ -0,0 +1,76 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+  async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .alter_table(
+        Table::alter()
+          .table(Message::Table)
+          .add_column(ColumnDef::new(Message::ReplyTo).uuid().null())
+          .to_owned(),
+      )
+      .await?;
+
+    manager
+      .alter_table(
+        Table::alter()
+          .table(Message::Table)
+          .add_column(ColumnDef::new(Message::ThreadRoot).uuid().null())
+          .to_owned(),
+      )
+      .await?;
+
+    manager
+      .create_index(
+        Index::create()
+          .name("idx-message-thread_root-sent")
+          .table(Message::Table)
+          .col(Message::ThreadRoot)
+          .col(Message::Sent)
+          .to_owned(),
+      )
+      .await
+  }
+
+  async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .drop_index(
+        Index::drop()
+          .name("idx-message-thread_root-sent")
+          .table(Message::Table)
+          .to_owned(),
+      )
+      .await?;
+
+    manager
+      .alter_table(
+        Table::alter()
+          .table(Message::Table)
+          .drop_column(Message::ThreadRoot)
+          .to_owned(),
+      )
+      .await?;
+
+    manager
+      .alter_table(
+        Table::alter()
+          .table(Message::Table)
+          .drop_column(Message::ReplyTo)
+          .to_owned(),
+      )
+      .await
+  }
+}
+
+/// Learn more at https://docs.rs/sea-query#iden
+#[derive(Iden)]
+enum Message {
+  Table,
+  Sent,
+  ReplyTo,
+  ThreadRoot,
+}
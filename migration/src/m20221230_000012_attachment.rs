@@ -0,0 +1,79 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+  async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .create_table(
+        Table::create()
+          .table(Attachment::Table)
+          .if_not_exists()
+          .col(
+            ColumnDef::new(Attachment::Id)
+              .uuid()
+              .not_null()
+              .primary_key(),
+          )
+          .col(ColumnDef::new(Attachment::Uploader).integer().not_null())
+          .col(ColumnDef::new(Attachment::Hash).string().not_null())
+          .col(ColumnDef::new(Attachment::Name).string().not_null())
+          .col(ColumnDef::new(Attachment::Mime).string().not_null())
+          .col(ColumnDef::new(Attachment::Size).big_integer().not_null())
+          .col(ColumnDef::new(Attachment::Uploaded).timestamp().not_null())
+          .to_owned(),
+      )
+      .await?;
+
+    manager
+      .create_table(
+        Table::create()
+          .table(AttachmentPost::Table)
+          .if_not_exists()
+          .col(ColumnDef::new(AttachmentPost::Attachment).uuid().not_null())
+          .col(ColumnDef::new(AttachmentPost::Message).uuid().not_null())
+          .col(ColumnDef::new(AttachmentPost::Room).integer().not_null())
+          .primary_key(
+            Index::create()
+              .col(AttachmentPost::Attachment)
+              .col(AttachmentPost::Message),
+          )
+          .to_owned(),
+      )
+      .await
+  }
+
+  async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .drop_table(Table::drop().table(AttachmentPost::Table).to_owned())
+      .await?;
+
+    manager
+      .drop_table(Table::drop().table(Attachment::Table).to_owned())
+      .await
+  }
+}
+
+/// Learn more at https://docs.rs/sea-query#iden
+#[derive(Iden)]
+enum Attachment {
+  Table,
+  Id,
+  Uploader,
+  Hash,
+  Name,
+  Mime,
+  Size,
+  Uploaded,
+}
+
+/// Which messages an attachment has been posted in.
+#[derive(Iden)]
+enum AttachmentPost {
+  Table,
+  Attachment,
+  Message,
+  Room,
+}
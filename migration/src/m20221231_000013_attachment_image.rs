@@ -0,0 +1,61 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+  async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .alter_table(
+        Table::alter()
+          .table(Attachment::Table)
+          .add_column(ColumnDef::new(Attachment::Width).integer().null())
+          .to_owned(),
+      )
+      .await?;
+
+    manager
+      .alter_table(
+        Table::alter()
+          .table(Attachment::Table)
+          .add_column(ColumnDef::new(Attachment::Height).integer().null())
+          .to_owned(),
+      )
+      .await?;
+
+    manager
+      .alter_table(
+        Table::alter()
+          .table(Attachment::Table)
+          .add_column(ColumnDef::new(Attachment::Thumbnail).string().null())
+          .to_owned(),
+      )
+      .await
+  }
+
+  async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    for column in [Attachment::Thumbnail, Attachment::Height, Attachment::Width] {
+      manager
+        .alter_table(
+          Table::alter()
+            .table(Attachment::Table)
+            .drop_column(column)
+            .to_owned(),
+        )
+        .await?;
+    }
+
+    Ok(())
+  }
+}
+
+/// Learn more at https://docs.rs/sea-query#iden
+#[derive(Iden)]
+enum Attachment {
+  Table,
+  Width,
+  Height,
+  /// The hash of the thumbnail blob.
+  Thumbnail,
+}
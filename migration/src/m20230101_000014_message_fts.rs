@@ -0,0 +1,68 @@
+use sea_orm_migration::{prelude::*, sea_orm::{ConnectionTrait, Statement}};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+/// The searchable text of `$msg.data`, `NULL` for deleted and system messages.
+const MSG_TEXT: &str = "CASE WHEN json_extract($msg.data, '$.type') = 'System' THEN NULL \
+  ELSE COALESCE(json_extract($msg.data, '$.text'), json_extract($msg.data, '$.code'), json_extract($msg.data, '$.name')) END";
+
+fn msg_text(msg: &str) -> String {
+  MSG_TEXT.replace("$msg", msg)
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+  async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    let new_text = msg_text("new");
+
+    let statements = [
+      "CREATE VIRTUAL TABLE message_fts USING fts5(uuid UNINDEXED, text)".to_string(),
+      format!(
+        "INSERT INTO message_fts (uuid, text) \
+          SELECT uuid, {text} FROM message WHERE {text} IS NOT NULL",
+        text = msg_text("message"),
+      ),
+      format!(
+        "CREATE TRIGGER message_fts_insert AFTER INSERT ON message \
+          WHEN {new_text} IS NOT NULL BEGIN \
+            INSERT INTO message_fts (uuid, text) VALUES (new.uuid, {new_text}); \
+          END"
+      ),
+      format!(
+        "CREATE TRIGGER message_fts_update AFTER UPDATE OF data ON message BEGIN \
+            DELETE FROM message_fts WHERE uuid = old.uuid; \
+            INSERT INTO message_fts (uuid, text) SELECT new.uuid, {new_text} WHERE {new_text} IS NOT NULL; \
+          END"
+      ),
+      "CREATE TRIGGER message_fts_delete AFTER DELETE ON message BEGIN \
+          DELETE FROM message_fts WHERE uuid = old.uuid; \
+        END".to_string(),
+    ];
+
+    let db = manager.get_connection();
+
+    for sql in statements {
+      db.execute(Statement::from_string(manager.get_database_backend(), sql)).await?;
+    }
+
+    Ok(())
+  }
+
+  async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    let statements = [
+      "DROP TRIGGER message_fts_delete",
+      "DROP TRIGGER message_fts_update",
+      "DROP TRIGGER message_fts_insert",
+      "DROP TABLE message_fts",
+    ];
+
+    let db = manager.get_connection();
+
+    for sql in statements {
+      db.execute(Statement::from_string(manager.get_database_backend(), sql.to_string())).await?;
+    }
+
+    Ok(())
+  }
+}
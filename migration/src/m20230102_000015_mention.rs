@@ -0,0 +1,50 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+  async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .create_table(
+        Table::create()
+          .table(Mention::Table)
+          .if_not_exists()
+          .col(ColumnDef::new(Mention::Message).uuid().not_null())
+          .col(ColumnDef::new(Mention::User).integer().not_null())
+          .col(ColumnDef::new(Mention::Room).integer().not_null())
+          .col(ColumnDef::new(Mention::Mentioned).timestamp().not_null())
+          .primary_key(Index::create().col(Mention::Message).col(Mention::User))
+          .to_owned(),
+      )
+      .await?;
+
+    manager
+      .create_index(
+        Index::create()
+          .name("idx-mention-user-mentioned")
+          .table(Mention::Table)
+          .col(Mention::User)
+          .col(Mention::Mentioned)
+          .to_owned(),
+      )
+      .await
+  }
+
+  async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .drop_table(Table::drop().table(Mention::Table).to_owned())
+      .await
+  }
+}
+
+/// Learn more at https://docs.rs/sea-query#iden
+#[derive(Iden)]
+enum Mention {
+  Table,
+  Message,
+  User,
+  Room,
+  Mentioned,
+}
@@ -0,0 +1,55 @@
+use sea_orm_migration::{prelude::*, sea_orm::{ConnectionTrait, Statement}};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+  async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .alter_table(
+        Table::alter()
+          .table(Member::Table)
+          .add_column(
+            ColumnDef::new(Member::Role)
+              .integer()
+              .not_null()
+              .default(0),
+          )
+          .to_owned(),
+      )
+      .await?;
+
+    // Rooms had no owner so far; the earliest member, usually the creator, becomes it.
+    manager
+      .get_connection()
+      .execute(Statement::from_string(
+        manager.get_database_backend(),
+        "UPDATE member SET role = 3 WHERE rowid IN ( \
+          SELECT (SELECT first.rowid FROM member first WHERE first.room = m.room ORDER BY first.joined, first.user LIMIT 1) \
+          FROM member m GROUP BY m.room \
+        )".to_string(),
+      ))
+      .await?;
+
+    Ok(())
+  }
+
+  async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .alter_table(
+        Table::alter()
+          .table(Member::Table)
+          .drop_column(Member::Role)
+          .to_owned(),
+      )
+      .await
+  }
+}
+
+/// Learn more at https://docs.rs/sea-query#iden
+#[derive(Iden)]
+enum Member {
+  Table,
+  Role,
+}
@@ -0,0 +1,78 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+  async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .alter_table(
+        Table::alter()
+          .table(Room::Table)
+          .add_column(
+            ColumnDef::new(Room::Visibility)
+              .integer()
+              .not_null()
+              .default(0),
+          )
+          .to_owned(),
+      )
+      .await?;
+
+    manager
+      .create_table(
+        Table::create()
+          .table(Invite::Table)
+          .if_not_exists()
+          .col(
+            ColumnDef::new(Invite::Code)
+              .string()
+              .not_null()
+              .primary_key(),
+          )
+          .col(ColumnDef::new(Invite::Room).integer().not_null())
+          .col(ColumnDef::new(Invite::Creator).integer().not_null())
+          .col(ColumnDef::new(Invite::Created).timestamp().not_null())
+          .col(ColumnDef::new(Invite::Expires).timestamp())
+          .col(ColumnDef::new(Invite::MaxUses).integer())
+          .col(ColumnDef::new(Invite::Uses).integer().not_null().default(0))
+          .to_owned(),
+      )
+      .await
+  }
+
+  async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .drop_table(Table::drop().table(Invite::Table).to_owned())
+      .await?;
+
+    manager
+      .alter_table(
+        Table::alter()
+          .table(Room::Table)
+          .drop_column(Room::Visibility)
+          .to_owned(),
+      )
+      .await
+  }
+}
+
+/// Learn more at https://docs.rs/sea-query#iden
+#[derive(Iden)]
+enum Room {
+  Table,
+  Visibility,
+}
+
+#[derive(Iden)]
+enum Invite {
+  Table,
+  Code,
+  Room,
+  Creator,
+  Created,
+  Expires,
+  MaxUses,
+  Uses,
+}
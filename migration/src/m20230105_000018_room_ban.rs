@@ -0,0 +1,43 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+  async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .create_table(
+        Table::create()
+          .table(Ban::Table)
+          .if_not_exists()
+          .col(ColumnDef::new(Ban::Room).integer().not_null())
+          .col(ColumnDef::new(Ban::User).integer().not_null())
+          .col(ColumnDef::new(Ban::By).integer().not_null())
+          .col(ColumnDef::new(Ban::Banned).timestamp().not_null())
+          .primary_key(
+            Index::create()
+              .col(Ban::Room)
+              .col(Ban::User),
+          )
+          .to_owned(),
+      )
+      .await
+  }
+
+  async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .drop_table(Table::drop().table(Ban::Table).to_owned())
+      .await
+  }
+}
+
+/// Learn more at https://docs.rs/sea-query#iden
+#[derive(Iden)]
+enum Ban {
+  Table,
+  Room,
+  User,
+  By,
+  Banned,
+}
@@ -0,0 +1,47 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+  async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .create_table(
+        Table::create()
+          .table(Dm::Table)
+          .if_not_exists()
+          .col(ColumnDef::new(Dm::UserA).integer().not_null())
+          .col(
+            ColumnDef::new(Dm::UserB)
+              .integer()
+              .not_null()
+              // Each pair is stored one way only, so that it cannot have two rooms.
+              .extra("CHECK (user_a < user_b)".to_string()),
+          )
+          .col(ColumnDef::new(Dm::Room).integer().not_null().unique_key())
+          .primary_key(
+            Index::create()
+              .col(Dm::UserA)
+              .col(Dm::UserB),
+          )
+          .to_owned(),
+      )
+      .await
+  }
+
+  async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .drop_table(Table::drop().table(Dm::Table).to_owned())
+      .await
+  }
+}
+
+/// Learn more at https://docs.rs/sea-query#iden
+#[derive(Iden)]
+enum Dm {
+  Table,
+  UserA,
+  UserB,
+  Room,
+}
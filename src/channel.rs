@@ -1,4 +1,16 @@
-use crate::msg::Msg;
+use std::{collections::{HashMap, HashSet}, time::{Duration, Instant}};
+
+use chrono::{DateTime, Local};
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use crate::{entities::room, msg::Msg, presence::{Presence, Availability}, role::Role};
+
+/// How many events may wait for a single connection before it lags behind.
+const CONN_QUEUE_SIZE: usize = 256;
+
+/// How many events a lagging connection may miss before it is dropped.
+const MAX_MISSED_EVENTS: usize = 1024;
 
 #[derive(Clone, Debug)]
 pub struct MsgEvent {
@@ -6,14 +18,109 @@ pub struct MsgEvent {
 }
 
 #[derive(Clone, Debug)]
-pub struct CloseEvent {
-  pub token: String,
+pub struct EditEvent {
+  pub msg: Msg,
+}
+
+#[derive(Clone, Debug)]
+pub struct DeleteEvent {
+  pub msg: Msg,
+}
+
+#[derive(Clone, Debug)]
+pub struct MentionEvent {
+  pub msg: Msg,
+}
+
+#[derive(Clone, Debug)]
+pub struct ReactionEvent {
+  pub user: i32,
+  pub room: i32,
+  pub uuid: Uuid,
+  pub emoji: String,
+  pub active: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct TypingEvent {
+  pub user: i32,
+  pub room: i32,
+  pub active: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct ReadEvent {
+  pub user: i32,
+  pub room: i32,
+  pub uuid: Uuid,
+}
+
+#[derive(Clone, Debug)]
+pub struct RoleEvent {
+  pub room: i32,
+  pub user: i32,
+  pub role: Role,
+}
+
+#[derive(Clone, Debug)]
+pub struct RemovedEvent {
+  pub room: i32,
+  pub user: i32,
+  /// Who kicked the user out, `None` if they left.
+  pub by: Option<i32>,
+  pub banned: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct RoomEvent {
+  pub room: room::Model,
+}
+
+#[derive(Clone, Debug)]
+pub struct ArchivedEvent {
+  pub room: i32,
+}
+
+#[derive(Clone, Debug)]
+pub struct PresenceEvent {
+  pub user: i32,
+  pub presence: Presence,
+  pub availability: Availability,
+}
+
+#[derive(Clone, Debug)]
+pub struct AckEvent {
+  pub uuid: Uuid,
+  pub sent: DateTime<Local>,
+  pub replay: bool,
+  pub request: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ErrorEvent {
+  pub code: i32,
+  pub msg: String,
+  pub request: Option<String>,
 }
 
 #[derive(Clone, Debug)]
 pub enum ChannelEvent {
   Msg(MsgEvent),
-  Close(CloseEvent),
+  Edit(EditEvent),
+  Delete(DeleteEvent),
+  Mention(MentionEvent),
+  Reaction(ReactionEvent),
+  Typing(TypingEvent),
+  Read(ReadEvent),
+  Role(RoleEvent),
+  Removed(RemovedEvent),
+  Room(RoomEvent),
+  Archived(ArchivedEvent),
+  Presence(PresenceEvent),
+  Ack(AckEvent),
+  Error(ErrorEvent),
+  /// The connection missed the events after this one and has to resync.
+  Lagged,
 }
 
 impl ChannelEvent {
@@ -21,7 +128,404 @@ impl ChannelEvent {
     Self::Msg(MsgEvent { msg })
   }
 
-  pub fn new_close(token: String) -> Self {
-    Self::Close(CloseEvent { token })
+  pub fn new_edit(msg: Msg) -> Self {
+    Self::Edit(EditEvent { msg })
+  }
+
+  pub fn new_delete(msg: Msg) -> Self {
+    Self::Delete(DeleteEvent { msg })
+  }
+
+  pub fn new_mention(msg: Msg) -> Self {
+    Self::Mention(MentionEvent { msg })
+  }
+
+  pub fn new_reaction(user: i32, msg: &Msg, emoji: String, active: bool) -> Self {
+    Self::Reaction(ReactionEvent { user, room: msg.room, uuid: msg.uuid, emoji, active })
+  }
+
+  pub fn new_typing(user: i32, room: i32, active: bool) -> Self {
+    Self::Typing(TypingEvent { user, room, active })
+  }
+
+  pub fn new_read(user: i32, msg: &Msg) -> Self {
+    Self::Read(ReadEvent { user, room: msg.room, uuid: msg.uuid })
+  }
+
+  pub fn new_role(room: i32, user: i32, role: Role) -> Self {
+    Self::Role(RoleEvent { room, user, role })
+  }
+
+  pub fn new_removed(room: i32, user: i32, by: Option<i32>, banned: bool) -> Self {
+    Self::Removed(RemovedEvent { room, user, by, banned })
+  }
+
+  pub fn new_room(room: room::Model) -> Self {
+    Self::Room(RoomEvent { room })
+  }
+
+  pub fn new_archived(room: i32) -> Self {
+    Self::Archived(ArchivedEvent { room })
+  }
+
+  pub fn new_presence(user: i32, presence: Presence, availability: Availability) -> Self {
+    Self::Presence(PresenceEvent { user, presence, availability })
+  }
+
+  pub fn new_ack(msg: &Msg, replay: bool, request: Option<String>) -> Self {
+    Self::Ack(AckEvent { uuid: msg.uuid, sent: msg.sent, replay, request })
+  }
+
+  pub fn new_error(code: i32, msg: String, request: Option<String>) -> Self {
+    Self::Error(ErrorEvent { code, msg, request })
+  }
+}
+
+pub type ConnId = u64;
+
+struct Conn {
+  sender: mpsc::Sender<ChannelEvent>,
+  /// Events missed since the queue got full, until the connection resyncs.
+  missed: usize,
+  /// When the client last sent an event, to tell whether the user is idle.
+  active: Instant,
+  /// Rooms the user was removed from while the connection was loading its rooms.
+  removed: Option<HashSet<i32>>,
+}
+
+impl Conn {
+  /// Queues `event`, returning `false` if the connection is hopelessly behind.
+  fn send(&mut self, event: ChannelEvent) -> bool {
+    if self.missed > 0 {
+      self.missed += 1;
+      return self.missed <= MAX_MISSED_EVENTS;
+    }
+
+    // The last slot is kept for telling the connection that it lags behind.
+    let event = if self.sender.capacity() > 1 {
+      event
+    } else {
+      self.missed = 1;
+      ChannelEvent::Lagged
+    };
+
+    !matches!(self.sender.try_send(event), Err(mpsc::error::TrySendError::Full(_)))
+  }
+}
+
+struct OnlineUser {
+  conns: HashMap<ConnId, Conn>,
+  rooms: HashSet<i32>,
+  availability: Availability,
+  /// The presence room members have last been told about.
+  announced: Presence,
+}
+
+/// Keeps track of the authenticated WebSocket connections and the rooms
+/// their users are in, so that events only go to the members of a room.
+#[derive(Default)]
+pub struct Registry {
+  next_conn: ConnId,
+  users: HashMap<i32, OnlineUser>,
+  rooms: HashMap<i32, HashSet<i32>>,
+  conn_users: HashMap<ConnId, i32>,
+  /// Who is typing in which room, with a counter telling their updates apart.
+  typing: HashMap<(i32, i32), u64>,
+  next_typing: u64,
+}
+
+impl Registry {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Registers a new connection of `user`; events for it arrive on the returned receiver.
+  pub fn connect(
+    &mut self,
+    user: i32,
+    availability: Availability,
+  ) -> (ConnId, mpsc::Receiver<ChannelEvent>) {
+    let (sender, receiver) = mpsc::channel(CONN_QUEUE_SIZE);
+
+    let conn = self.next_conn;
+    self.next_conn += 1;
+
+    let online = self.users.entry(user).or_insert_with(|| OnlineUser {
+      conns: HashMap::new(),
+      rooms: HashSet::new(),
+      availability,
+      announced: Presence::Offline,
+    });
+
+    online.conns.insert(conn, Conn { sender, missed: 0, active: Instant::now(), removed: Some(HashSet::new()) });
+    self.conn_users.insert(conn, user);
+
+    (conn, receiver)
+  }
+
+  /// Unregisters a connection, which closes its receiver.
+  pub fn disconnect(&mut self, conn: ConnId) {
+    let Some(user) = self.conn_users.remove(&conn) else {
+      return;
+    };
+
+    let Some(online) = self.users.get_mut(&user) else {
+      return;
+    };
+
+    online.conns.remove(&conn);
+
+    if online.conns.is_empty() {
+      let availability = online.availability;
+
+      self.announce(user, Presence::Offline, availability);
+
+      let Some(online) = self.users.remove(&user) else {
+        return;
+      };
+
+      for room in online.rooms {
+        self.remove_from_room(user, room);
+      }
+    }
+  }
+
+  /// Starts delivering the events of the rooms `user` was in when `conn` connected,
+  /// skipping those they have been removed from since. Returns the rooms joined.
+  pub fn join_loaded(&mut self, conn: ConnId, user: i32, rooms: Vec<i32>) -> Vec<i32> {
+    let removed = self.get_conn(conn)
+      .and_then(|conn| conn.removed.take())
+      .unwrap_or_default();
+
+    let rooms: Vec<i32> = rooms.into_iter()
+      .filter(|room| !removed.contains(room))
+      .collect();
+
+    for room in &rooms {
+      self.join(user, *room);
+    }
+
+    rooms
+  }
+
+  /// Starts delivering the events of `room` to `user` if they are online.
+  pub fn join(&mut self, user: i32, room: i32) {
+    let Some(online) = self.users.get_mut(&user) else {
+      return;
+    };
+
+    online.rooms.insert(room);
+    self.rooms.entry(room).or_default().insert(user);
+  }
+
+  /// Stops delivering the events of `room` to `user`.
+  pub fn leave(&mut self, user: i32, room: i32) {
+    if let Some(online) = self.users.get_mut(&user) {
+      online.rooms.remove(&room);
+    }
+
+    self.typing.remove(&(user, room));
+    self.remove_from_room(user, room);
+    self.record_removal(Some(user), room);
+  }
+
+  /// Stops delivering the events of `room` to anyone.
+  pub fn drop_room(&mut self, room: i32) {
+    for user in self.rooms.remove(&room).unwrap_or_default() {
+      if let Some(online) = self.users.get_mut(&user) {
+        online.rooms.remove(&room);
+      }
+    }
+
+    self.typing.retain(|&(_, typing_room), _| typing_room != room);
+    self.record_removal(None, room);
+  }
+
+  /// Keeps connections still loading their rooms from joining `room`,
+  /// for `user` or for everyone.
+  fn record_removal(&mut self, user: Option<i32>, room: i32) {
+    let users = self.users.iter_mut()
+      .filter(|(id, _)| user.is_none_or(|user| user == **id));
+
+    for (_, online) in users {
+      for conn in online.conns.values_mut() {
+        if let Some(removed) = &mut conn.removed {
+          removed.insert(room);
+        }
+      }
+    }
+  }
+
+  fn remove_from_room(&mut self, user: i32, room: i32) {
+    if let Some(members) = self.rooms.get_mut(&room) {
+      members.remove(&user);
+
+      if members.is_empty() {
+        self.rooms.remove(&room);
+      }
+    }
+  }
+
+  /// Returns the presence of `user` and how many connections they have.
+  pub fn presence(&self, user: i32, idle_after: Duration) -> (Presence, usize) {
+    let Some(online) = self.users.get(&user) else {
+      return (Presence::Offline, 0);
+    };
+
+    let idle = online.conns.values()
+      .all(|conn| conn.active.elapsed() > idle_after);
+
+    let presence = if idle { Presence::Idle } else { Presence::Online };
+
+    (presence, online.conns.len())
+  }
+
+  /// Records that the client on `conn` did something.
+  pub fn touch(&mut self, conn: ConnId) {
+    if let Some(conn) = self.get_conn(conn) {
+      conn.active = Instant::now();
+    }
+  }
+
+  pub fn set_availability(&mut self, user: i32, availability: Availability) {
+    let Some(online) = self.users.get_mut(&user) else {
+      return;
+    };
+
+    if online.availability != availability {
+      online.availability = availability;
+
+      let presence = online.announced;
+      self.announce(user, presence, availability);
+    }
+  }
+
+  /// Tells room members about `user` if their presence changed since last time.
+  pub fn refresh_presence(&mut self, user: i32, idle_after: Duration) {
+    let (presence, _) = self.presence(user, idle_after);
+
+    let Some(online) = self.users.get_mut(&user) else {
+      return;
+    };
+
+    if online.announced != presence {
+      online.announced = presence;
+
+      let availability = online.availability;
+      self.announce(user, presence, availability);
+    }
+  }
+
+  pub fn refresh_all_presence(&mut self, idle_after: Duration) {
+    let users: Vec<i32> = self.users.keys().copied().collect();
+
+    for user in users {
+      self.refresh_presence(user, idle_after);
+    }
+  }
+
+  /// Sends the presence of `user` to everyone online sharing a room with them.
+  fn announce(&mut self, user: i32, presence: Presence, availability: Availability) {
+    let Some(online) = self.users.get(&user) else {
+      return;
+    };
+
+    let peers: HashSet<i32> = online.rooms.iter()
+      .filter_map(|room| self.rooms.get(room))
+      .flatten()
+      .copied()
+      .collect();
+
+    for peer in peers {
+      self.send_to_user(peer, ChannelEvent::new_presence(user, presence, availability));
+    }
+  }
+
+  pub fn in_room(&self, user: i32, room: i32) -> bool {
+    self.rooms.get(&room)
+      .is_some_and(|members| members.contains(&user))
+  }
+
+  /// Records that `user` is typing in `room`, returning an id for this update.
+  pub fn start_typing(&mut self, user: i32, room: i32) -> u64 {
+    let update = self.next_typing;
+    self.next_typing += 1;
+
+    self.typing.insert((user, room), update);
+
+    update
+  }
+
+  /// Records that `user` stopped typing in `room`, unless `update` has been
+  /// superseded; returns whether they were typing before.
+  pub fn stop_typing(&mut self, user: i32, room: i32, update: Option<u64>) -> bool {
+    match (self.typing.get(&(user, room)), update) {
+      (Some(current), Some(update)) if *current != update => false,
+      (Some(_), _) => {
+        self.typing.remove(&(user, room));
+        true
+      },
+      (None, _) => false,
+    }
+  }
+
+  /// Marks a lagging connection as caught up again.
+  pub fn resync(&mut self, conn: ConnId) {
+    if let Some(conn) = self.get_conn(conn) {
+      conn.missed = 0;
+    }
+  }
+
+  fn get_conn(&mut self, conn: ConnId) -> Option<&mut Conn> {
+    let user = self.conn_users.get(&conn)?;
+
+    self.users.get_mut(user)?
+      .conns.get_mut(&conn)
+  }
+
+  pub fn send_to_conn(&mut self, conn: ConnId, event: ChannelEvent) {
+    let Some(conn_ref) = self.get_conn(conn) else {
+      return;
+    };
+
+    if !conn_ref.send(event) {
+      warn!("[channel] Connection `{conn}` is too slow, disconnecting it!");
+      self.disconnect(conn);
+    }
+  }
+
+  pub fn send_to_user(&mut self, user: i32, event: ChannelEvent) {
+    let Some(online) = self.users.get_mut(&user) else {
+      return;
+    };
+
+    let slow: Vec<ConnId> = online.conns.iter_mut()
+      .filter_map(|(id, conn)| (!conn.send(event.clone())).then_some(*id))
+      .collect();
+
+    for conn in slow {
+      warn!("[channel] Connection `{conn}` is too slow, disconnecting it!");
+      self.disconnect(conn);
+    }
+  }
+
+  pub fn send_to_room(&mut self, room: i32, event: ChannelEvent) {
+    self.send_to_room_except(room, None, event);
+  }
+
+  /// Sends `event` to the members of `room` other than `except`.
+  pub fn send_to_room_except(&mut self, room: i32, except: Option<i32>, event: ChannelEvent) {
+    let Some(members) = self.rooms.get(&room) else {
+      return;
+    };
+
+    let members: Vec<i32> = members.iter()
+      .copied()
+      .filter(|user| Some(*user) != except)
+      .collect();
+
+    for user in members {
+      self.send_to_user(user, event.clone());
+    }
   }
 }
@@ -0,0 +1,52 @@
+use std::{env, fmt::Display, path::PathBuf, str::FromStr, time::Duration};
+
+pub struct Config {
+  /// How often the server pings each WebSocket connection.
+  pub ping_interval: Duration,
+  /// How long after a missed ping a silent connection is dropped.
+  pub ping_timeout: Duration,
+  /// How long a new WebSocket connection may take to authenticate.
+  pub auth_timeout: Duration,
+  /// How long a user may do nothing on any connection before going idle.
+  pub idle_after: Duration,
+  /// Where uploaded files are stored.
+  pub upload_dir: PathBuf,
+  /// The largest file that can be uploaded, in bytes.
+  pub max_upload_size: usize,
+}
+
+impl Config {
+  pub fn from_env() -> Self {
+    Self {
+      ping_interval: secs_from_env("CHATOY_PING_INTERVAL", 30),
+      ping_timeout: secs_from_env("CHATOY_PING_TIMEOUT", 10),
+      auth_timeout: secs_from_env("CHATOY_AUTH_TIMEOUT", 10),
+      idle_after: secs_from_env("CHATOY_IDLE_AFTER", 300),
+      upload_dir: env::var("CHATOY_UPLOAD_DIR").unwrap_or_else(|_| "./uploads".to_string()).into(),
+      max_upload_size: parse_from_env("CHATOY_MAX_UPLOAD_SIZE", 10 * 1024 * 1024),
+    }
+  }
+}
+
+fn parse_from_env<T: FromStr + Display>(key: &str, default: T) -> T {
+  match env::var(key) {
+    Err(_) => default,
+    Ok(value) => value.parse().unwrap_or_else(|_| {
+      warn!("Invalid `{key}`: `{value}`, using `{default}` instead!");
+      default
+    }),
+  }
+}
+
+/// Zero is refused like an invalid value, as none of the durations can be zero.
+fn secs_from_env(key: &str, default: u64) -> Duration {
+  let secs = match parse_from_env(key, default) {
+    0 => {
+      warn!("`{key}` must be at least 1 second, using `{default}` instead!");
+      default
+    },
+    secs => secs,
+  };
+
+  Duration::from_secs(secs)
+}
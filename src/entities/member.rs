@@ -11,6 +11,8 @@ pub struct Model {
   #[sea_orm(primary_key, auto_increment = false)]
   pub room: i32,
   pub joined: DateTimeLocal,
+  pub last_read: Option<Uuid>,
+  pub role: i32,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -2,7 +2,16 @@
 
 pub mod prelude;
 
+pub mod attachment;
+pub mod attachment_post;
+pub mod ban;
+pub mod dm;
+pub mod invite;
 pub mod member;
+pub mod mention;
+pub mod message;
+pub mod message_revision;
+pub mod reaction;
 pub mod room;
 pub mod session;
 pub mod user;
@@ -1,6 +1,15 @@
 //! `SeaORM` Entity. Generated by sea-orm-codegen 0.10.6
 
+pub use super::attachment::Entity as Attachment;
+pub use super::attachment_post::Entity as AttachmentPost;
+pub use super::ban::Entity as Ban;
+pub use super::dm::Entity as Dm;
+pub use super::invite::Entity as Invite;
 pub use super::member::Entity as Member;
+pub use super::mention::Entity as Mention;
+pub use super::message::Entity as Message;
+pub use super::message_revision::Entity as MessageRevision;
+pub use super::reaction::Entity as Reaction;
 pub use super::room::Entity as Room;
 pub use super::session::Entity as Session;
 pub use super::user::Entity as User;
@@ -11,6 +11,10 @@ pub struct Model {
   pub name: String,
   pub description: String,
   pub created: DateTimeLocal,
+  pub visibility: i32,
+  pub avatar: Option<Uuid>,
+  pub topic: String,
+  pub archived: Option<DateTimeLocal>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
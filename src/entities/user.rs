@@ -14,6 +14,7 @@ pub struct Model {
   pub slogan: String,
   pub status: i32,
   pub registered: DateTimeLocal,
+  pub availability: i32,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -4,22 +4,26 @@ mod routers;
 mod msg;
 mod channel;
 mod ws;
+mod config;
+mod presence;
+mod media;
+mod role;
 
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
-use tokio::sync::broadcast;
 use tower_http::cors::{CorsLayer, self};
-use axum::{Router, routing::{get, post}, http::{self, Method}};
+use axum::{Router, routing::{get, post, delete}, http::{self, Method}, extract::DefaultBodyLimit};
 use sea_orm::{Database, DatabaseConnection};
 
-use crate::channel::ChannelEvent;
+use crate::{channel::Registry, config::Config};
 
 #[macro_use]
 extern crate log;
 
 pub struct AppState {
   db: DatabaseConnection,
-  sender: broadcast::Sender<ChannelEvent>,
+  registry: Mutex<Registry>,
+  config: Config,
 }
 
 #[tokio::main]
@@ -31,11 +35,16 @@ async fn main() {
 
   info!("Database connected!");
 
-  let (sender, _) = broadcast::channel::<ChannelEvent>(256);
+  let shared_state = Arc::new(AppState {
+    db,
+    registry: Mutex::new(Registry::new()),
+    config: Config::from_env(),
+  });
 
-  info!("Broadcast channel created!");
+  tokio::fs::create_dir_all(&shared_state.config.upload_dir).await
+    .expect("Error creating the upload directory!");
 
-  let shared_state = Arc::new(AppState { db, sender });
+  tokio::spawn(presence::sweep(shared_state.clone()));
 
   let app = Router::new()
     .route("/", get(|| async { "Hello, Chatoy!" }))
@@ -44,17 +53,47 @@ async fn main() {
     .route("/users", post(routers::register))
     .route("/users/:id", get(routers::get_user))
     .route("/users", get(routers::get_user_list))
+    .route("/users/:id/presence", get(routers::get_presence))
+    .route("/users/me/availability", post(routers::set_availability))
+    .route("/presence", get(routers::get_presence_list))
     .route("/sessions", get(routers::get_session_list))
     .route("/rooms", post(routers::new_room))
-    .route("/rooms/:id", get(routers::get_room))
+    .route(
+      "/rooms/:id",
+      get(routers::get_room)
+        .patch(routers::edit_room)
+        .delete(routers::archive_room),
+    )
     .route("/rooms/me", get(routers::get_my_room))
     .route("/rooms", get(routers::get_room_list))
     .route("/members", get(routers::get_member_list))
     .route("/rooms/:id/join", post(routers::join_room))
+    .route("/rooms/:id/messages", get(routers::get_room_msgs))
+    .route("/rooms/:id/messages/:uuid", delete(routers::delete_msg))
+    .route("/rooms/:id/messages/:uuid/revisions", get(routers::get_msg_revisions))
+    .route("/rooms/:id/threads/:uuid", get(routers::get_thread_msgs))
+    .route("/rooms/:id/read", post(routers::mark_read))
+    .route("/rooms/:id/members/:user/role", post(routers::set_member_role))
+    .route("/rooms/:id/leave", post(routers::leave_room))
+    .route("/rooms/:id/members/:user/kick", post(routers::kick_member))
+    .route("/rooms/:id/bans", get(routers::get_ban_list))
+    .route("/rooms/:id/bans/:user", delete(routers::unban_user))
+    .route("/rooms/:id/invites", post(routers::new_invite))
+    .route("/invites/:code/accept", post(routers::accept_invite))
+    .route("/dms/:user_id", post(routers::get_dm))
+    .route(
+      "/uploads",
+      post(routers::upload_file)
+        .layer(DefaultBodyLimit::max(shared_state.config.max_upload_size)),
+    )
+    .route("/uploads/:id", get(routers::download_file))
+    .route("/uploads/:id/thumbnail", get(routers::download_thumbnail))
+    .route("/search", get(routers::search_msgs))
+    .route("/mentions", get(routers::get_mentions))
     .layer(
       CorsLayer::new()
         .allow_origin(cors::Any)
-        .allow_methods(vec![Method::GET, Method::POST])
+        .allow_methods(vec![Method::GET, Method::POST, Method::PATCH, Method::DELETE])
         .allow_headers(vec![
           http::header::CONTENT_TYPE,
           http::header::AUTHORIZATION,
@@ -0,0 +1,420 @@
+use std::io::Cursor;
+
+use image::{DynamicImage, ImageOutputFormat, io::{Limits, Reader}};
+
+/// The size of the box thumbnails are fitted in, in pixels.
+const THUMBNAIL_SIZE: u32 = 320;
+
+const THUMBNAIL_QUALITY: u8 = 80;
+
+/// The largest width and height of an image that is decoded, in pixels.
+const MAX_IMAGE_SIZE: u32 = 8192;
+
+/// How much memory decoding an image may take, so that a small file claiming
+/// huge dimensions cannot exhaust it.
+const MAX_IMAGE_ALLOC: u64 = 128 * 1024 * 1024;
+
+/// What is learned from an uploaded file that turns out to be an image.
+pub struct ImageInfo {
+  pub mime: &'static str,
+  pub width: u32,
+  pub height: u32,
+  /// `None` if the image is small enough to be its own thumbnail.
+  pub thumbnail: Option<Vec<u8>>,
+}
+
+/// Decodes `data` as an image, returning `None` if it is not one or too large.
+/// This blocks for a while, so it should be run off the async workers.
+pub fn process_image(data: &[u8]) -> Option<ImageInfo> {
+  let format = image::guess_format(data).ok()?;
+
+  let mut limits = Limits::default();
+  limits.max_image_width = Some(MAX_IMAGE_SIZE);
+  limits.max_image_height = Some(MAX_IMAGE_SIZE);
+  limits.max_alloc = Some(MAX_IMAGE_ALLOC);
+
+  let mut reader = Reader::with_format(Cursor::new(data), format);
+  reader.limits(limits);
+
+  let image = match reader.decode() {
+    Ok(image) => image,
+    Err(err) => {
+      info!("[media] Not decoding the image: {err}");
+      return None;
+    },
+  };
+
+  let (width, height) = (image.width(), image.height());
+
+  let thumbnail = if width > THUMBNAIL_SIZE || height > THUMBNAIL_SIZE {
+    encode_thumbnail(image.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE))
+  } else {
+    None
+  };
+
+  Some(ImageInfo { mime: format.to_mime_type(), width, height, thumbnail })
+}
+
+/// Encodes as JPEG, or as PNG if there is transparency to keep.
+fn encode_thumbnail(thumbnail: DynamicImage) -> Option<Vec<u8>> {
+  let mut buf = Cursor::new(vec![]);
+
+  let result = if thumbnail.color().has_alpha() {
+    thumbnail.write_to(&mut buf, ImageOutputFormat::Png)
+  } else {
+    DynamicImage::ImageRgb8(thumbnail.to_rgb8())
+      .write_to(&mut buf, ImageOutputFormat::Jpeg(THUMBNAIL_QUALITY))
+  };
+
+  if let Err(err) = result {
+    error!("[media] Failed to encode a thumbnail: {err}");
+    return None;
+  }
+
+  Some(buf.into_inner())
+}
+
+/// The MIME type of an image, if `data` is one.
+pub fn image_mime(data: &[u8]) -> Option<&'static str> {
+  image::guess_format(data).ok().map(|format| format.to_mime_type())
+}
+
+/// Clears the GPS data from the EXIF metadata of a JPEG, PNG or WebP image in
+/// place, leaving everything else, like the orientation, as it is.
+pub fn strip_location(data: &mut [u8]) {
+  if data.starts_with(&[0xFF, 0xD8]) {
+    strip_jpeg(data);
+  } else if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+    strip_png(data);
+  } else if data.starts_with(b"RIFF") && data.get(8..12) == Some(b"WEBP") {
+    strip_webp(data);
+  }
+}
+
+const EXIF_HEADER: &[u8] = b"Exif\0\0";
+
+fn strip_jpeg(data: &mut [u8]) {
+  let mut pos = 2;
+
+  while pos + 4 <= data.len() && data[pos] == 0xFF {
+    let marker = data[pos + 1];
+
+    match marker {
+      // Fill bytes before a marker.
+      0xFF => {
+        pos += 1;
+        continue;
+      },
+      // Markers without a segment.
+      0x01 | 0xD0..=0xD7 => {
+        pos += 2;
+        continue;
+      },
+      // Compressed data or the end of the image; metadata comes before.
+      0xD9 | 0xDA => break,
+      _ => (),
+    }
+
+    let len = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+
+    // The length counts its own two bytes, anything less is corrupt.
+    if len < 2 {
+      break;
+    }
+
+    let end = (pos + 2 + len).min(data.len());
+
+    if marker == 0xE1 {
+      if let Some(segment) = data.get_mut(pos + 4..end) {
+        if segment.starts_with(EXIF_HEADER) {
+          clear_gps(&mut segment[EXIF_HEADER.len()..]);
+        }
+      }
+    }
+
+    pos += 2 + len;
+  }
+}
+
+fn strip_png(data: &mut [u8]) {
+  let mut pos = 8;
+
+  while pos + 12 <= data.len() {
+    let len = u32::from_be_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]) as usize;
+
+    let Some(end) = (pos + 8).checked_add(len).filter(|end| end + 4 <= data.len()) else {
+      break;
+    };
+
+    if &data[pos + 4..pos + 8] == b"eXIf" {
+      clear_gps(&mut data[pos + 8..end]);
+
+      let crc = crc32(&data[pos + 4..end]);
+      data[end..end + 4].copy_from_slice(&crc.to_be_bytes());
+    }
+
+    pos = end + 4;
+  }
+}
+
+fn strip_webp(data: &mut [u8]) {
+  let mut pos = 12;
+
+  while pos + 8 <= data.len() {
+    let len = u32::from_le_bytes([data[pos + 4], data[pos + 5], data[pos + 6], data[pos + 7]]) as usize;
+    let end = (pos + 8).saturating_add(len).min(data.len());
+
+    if &data[pos..pos + 4] == b"EXIF" {
+      if let Some(exif) = data.get_mut(pos + 8..end) {
+        if exif.starts_with(EXIF_HEADER) {
+          clear_gps(&mut exif[EXIF_HEADER.len()..]);
+        } else {
+          clear_gps(exif);
+        }
+      }
+    }
+
+    // Chunks are padded to an even size.
+    pos = end + len % 2;
+  }
+}
+
+/// The EXIF tag pointing to the GPS data.
+const GPS_IFD_TAG: usize = 0x8825;
+
+/// Zeroes the GPS directory of the TIFF structure EXIF is stored in, leaving
+/// an empty directory behind so that every other offset stays valid.
+fn clear_gps(tiff: &mut [u8]) -> Option<()> {
+  let big_endian = match tiff.get(..2)? {
+    b"MM" => true,
+    b"II" => false,
+    _ => return None,
+  };
+
+  let mut tiff = Tiff { data: tiff, big_endian };
+
+  let ifd0 = tiff.read_u32(4)?;
+  let entries = tiff.read_u16(ifd0)?;
+
+  let gps = (0..entries)
+    .map(|i| ifd0 + 2 + i * 12)
+    .find(|&entry| tiff.read_u16(entry) == Some(GPS_IFD_TAG))
+    .and_then(|entry| tiff.read_u32(entry + 8))?;
+
+  let entries = tiff.read_u16(gps)?;
+
+  for entry in (0..entries).map(|i| gps + 2 + i * 12) {
+    let size = value_size(tiff.read_u16(entry + 2)?) * tiff.read_u32(entry + 4)?;
+
+    // Values of up to 4 bytes are stored in the entry itself.
+    if size > 4 {
+      let offset = tiff.read_u32(entry + 8)?;
+      tiff.zero(offset, size);
+    }
+  }
+
+  // Also zeroes the entry count, and the offset of the next directory after it.
+  tiff.zero(gps, 2 + entries * 12 + 4);
+
+  Some(())
+}
+
+struct Tiff<'a> {
+  data: &'a mut [u8],
+  big_endian: bool,
+}
+
+impl Tiff<'_> {
+  fn read_u16(&self, at: usize) -> Option<usize> {
+    let bytes = self.data.get(at..at + 2)?.try_into().ok()?;
+
+    Some(if self.big_endian { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) } as usize)
+  }
+
+  fn read_u32(&self, at: usize) -> Option<usize> {
+    let bytes = self.data.get(at..at + 4)?.try_into().ok()?;
+
+    Some(if self.big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) } as usize)
+  }
+
+  fn zero(&mut self, at: usize, len: usize) {
+    let start = at.min(self.data.len());
+    let end = at.saturating_add(len).min(self.data.len());
+
+    self.data[start..end].fill(0);
+  }
+}
+
+/// The size of a single value of a TIFF field type, in bytes.
+fn value_size(field_type: usize) -> usize {
+  match field_type {
+    3 | 8 => 2,
+    4 | 9 | 11 => 4,
+    5 | 10 | 12 => 8,
+    _ => 1,
+  }
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+  let mut crc = !0u32;
+
+  for &byte in bytes {
+    crc ^= byte as u32;
+
+    for _ in 0..8 {
+      crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+    }
+  }
+
+  !crc
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Where the GPS directory starts in the TIFF built by `tiff`.
+  const GPS_START: usize = 38;
+
+  /// A little endian TIFF with an orientation and a GPS directory holding a
+  /// latitude reference in its entry and the latitude itself after it.
+  fn tiff() -> Vec<u8> {
+    let mut tiff = b"II\x2A\0".to_vec();
+    tiff.extend(8u32.to_le_bytes());
+
+    // IFD0: the orientation, then the pointer to the GPS directory.
+    tiff.extend(2u16.to_le_bytes());
+    tiff.extend([0x12, 0x01, 3, 0, 1, 0, 0, 0, 6, 0, 0, 0]);
+    tiff.extend([0x25, 0x88, 4, 0, 1, 0, 0, 0]);
+    tiff.extend((GPS_START as u32).to_le_bytes());
+    tiff.extend(0u32.to_le_bytes());
+
+    // The GPS directory, with three rationals stored after it.
+    tiff.extend(2u16.to_le_bytes());
+    tiff.extend([1, 0, 2, 0, 2, 0, 0, 0, b'N', 0, 0, 0]);
+    tiff.extend([2, 0, 5, 0, 3, 0, 0, 0]);
+    tiff.extend((GPS_START as u32 + 30).to_le_bytes());
+    tiff.extend(0u32.to_le_bytes());
+    tiff.extend([0x11; 24]);
+
+    tiff
+  }
+
+  fn jpeg(exif: &[u8]) -> Vec<u8> {
+    let mut data = vec![0xFF, 0xD8, 0xFF, 0xE1];
+    data.extend((2 + EXIF_HEADER.len() as u16 + exif.len() as u16).to_be_bytes());
+    data.extend(EXIF_HEADER);
+    data.extend(exif);
+    data.extend([0xFF, 0xD9]);
+    data
+  }
+
+  fn png_chunk(kind: &[u8], body: &[u8]) -> Vec<u8> {
+    let mut chunk = (body.len() as u32).to_be_bytes().to_vec();
+    chunk.extend(kind);
+    chunk.extend(body);
+    chunk.extend(crc32(&chunk[4..]).to_be_bytes());
+    chunk
+  }
+
+  fn png(exif: &[u8]) -> Vec<u8> {
+    let mut data = b"\x89PNG\r\n\x1a\n".to_vec();
+    data.extend(png_chunk(b"eXIf", exif));
+    data.extend(png_chunk(b"IEND", b""));
+    data
+  }
+
+  fn webp(exif: &[u8]) -> Vec<u8> {
+    let mut chunk = b"EXIF".to_vec();
+    chunk.extend((EXIF_HEADER.len() as u32 + exif.len() as u32).to_le_bytes());
+    chunk.extend(EXIF_HEADER);
+    chunk.extend(exif);
+
+    let mut data = b"RIFF".to_vec();
+    data.extend((4 + chunk.len() as u32).to_le_bytes());
+    data.extend(b"WEBP");
+    data.extend(chunk);
+    data
+  }
+
+  /// Checks that the TIFF at `start` of `data` lost its GPS data and nothing else.
+  fn assert_stripped(data: &[u8], start: usize) {
+    let original = tiff();
+    let stripped = &data[start..start + original.len()];
+
+    assert_eq!(stripped[..GPS_START], original[..GPS_START]);
+    assert!(stripped[GPS_START..].iter().all(|&byte| byte == 0));
+  }
+
+  #[test]
+  fn strips_gps_from_jpeg() {
+    let mut data = jpeg(&tiff());
+    strip_location(&mut data);
+
+    assert_stripped(&data, 4 + 2 + EXIF_HEADER.len());
+  }
+
+  #[test]
+  fn strips_gps_from_png() {
+    let mut data = png(&tiff());
+    strip_location(&mut data);
+
+    assert_stripped(&data, 8 + 8);
+
+    let end = 8 + 8 + tiff().len();
+    assert_eq!(data[end..end + 4], crc32(&data[8 + 4..end]).to_be_bytes());
+  }
+
+  #[test]
+  fn strips_gps_from_webp() {
+    let mut data = webp(&tiff());
+    strip_location(&mut data);
+
+    assert_stripped(&data, 12 + 8 + EXIF_HEADER.len());
+  }
+
+  #[test]
+  fn crc32_matches_known_values() {
+    assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    assert_eq!(crc32(b"IEND"), 0xAE42_6082);
+  }
+
+  #[test]
+  fn survives_truncated_images() {
+    for image in [jpeg(&tiff()), png(&tiff()), webp(&tiff())] {
+      for len in 0..image.len() {
+        strip_location(&mut image[..len].to_vec());
+      }
+    }
+  }
+
+  #[test]
+  fn survives_oversized_lengths() {
+    // JPEG segments claiming more than there is, and less than their own length.
+    for len in [0xFFFF, 0, 1] {
+      let mut data = jpeg(&tiff());
+      data[4..6].copy_from_slice(&(len as u16).to_be_bytes());
+      strip_location(&mut data);
+    }
+
+    let mut data = png(&tiff());
+    data[8..12].copy_from_slice(&u32::MAX.to_be_bytes());
+    strip_location(&mut data);
+
+    let mut data = webp(&tiff());
+    data[16..20].copy_from_slice(&u32::MAX.to_le_bytes());
+    strip_location(&mut data);
+  }
+
+  #[test]
+  fn survives_corrupt_tiff_offsets() {
+    // The GPS pointer, then the GPS entry count and the latitude count and offset.
+    for (at, value) in [(30, u32::MAX), (GPS_START, u32::MAX), (GPS_START + 18, u32::MAX), (GPS_START + 22, u32::MAX)] {
+      let mut exif = tiff();
+      exif[at..at + 4].copy_from_slice(&value.to_le_bytes());
+
+      let mut data = jpeg(&exif);
+      strip_location(&mut data);
+    }
+  }
+}
@@ -1,16 +1,198 @@
+use anyhow::Result;
 use serde::{Deserialize, Serialize};
+use sea_orm::ActiveValue;
 use uuid::Uuid;
 use chrono::{DateTime, Local};
 
+use crate::entities::{message, message_revision};
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct TextMsg {
   text: String,
 }
 
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CodeMsg {
+  /// The language to highlight the code as, like `rust`.
+  pub language: Option<String>,
+  pub code: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct QuoteMsg {
+  pub text: String,
+  /// Who or what is quoted.
+  pub source: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct LocationMsg {
+  pub latitude: f64,
+  pub longitude: f64,
+  pub name: Option<String>,
+}
+
+/// An uploaded file; everything but the id is filled in by the server.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FileMsg {
+  pub id: Uuid,
+  #[serde(default)]
+  pub name: String,
+  #[serde(default)]
+  pub mime: String,
+  #[serde(default)]
+  pub size: u64,
+}
+
+/// An uploaded image; everything but the id is filled in by the server.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ImageMsg {
+  pub id: Uuid,
+  #[serde(default)]
+  pub name: String,
+  #[serde(default)]
+  pub mime: String,
+  #[serde(default)]
+  pub size: u64,
+  #[serde(default)]
+  pub width: u32,
+  #[serde(default)]
+  pub height: u32,
+  /// Where to download a small version of the image from.
+  #[serde(default)]
+  pub thumbnail: String,
+}
+
+/// Something that happened in a room, posted by the server.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "event")]
+pub enum SystemMsg {
+  Joined {
+    user: i32,
+  },
+  Renamed {
+    user: i32,
+    name: String,
+  },
+  Left {
+    user: i32,
+  },
+  Kicked {
+    user: i32,
+    by: i32,
+  },
+  Banned {
+    user: i32,
+    by: i32,
+  },
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(tag = "type")]
 pub enum MsgContent {
   Text(TextMsg),
+  Code(CodeMsg),
+  Quote(QuoteMsg),
+  Location(LocationMsg),
+  File(FileMsg),
+  Image(ImageMsg),
+  System(SystemMsg),
+}
+
+/// How many different users one message may mention.
+const MAX_MENTIONS: usize = 20;
+
+/// Whether `c` can be part of a mentioned username.
+fn in_username(c: char) -> bool {
+  c.is_alphanumeric() || matches!(c, '_' | '-' | '.')
+}
+
+/// How many characters of a message are shown when it is replied to.
+const PREVIEW_LEN: usize = 100;
+
+impl MsgContent {
+  /// The uploaded file the content refers to.
+  pub fn attachment(&self) -> Option<Uuid> {
+    match self {
+      Self::File(file) => Some(file.id),
+      Self::Image(image) => Some(image.id),
+      _ => None,
+    }
+  }
+
+  /// The usernames mentioned with `@username` in a text message.
+  pub fn mentions(&self) -> Vec<String> {
+    let Self::Text(text) = self else {
+      return vec![];
+    };
+
+    let mut mentions: Vec<String> = vec![];
+    let mut prev = None;
+
+    for (i, c) in text.text.char_indices() {
+      // An `@` inside a word, like in an email address, is no mention.
+      if c == '@' && !prev.is_some_and(in_username) {
+        let rest = &text.text[i + 1..];
+        let end = rest.find(|c| !in_username(c)).unwrap_or(rest.len());
+        // A full stop ending the sentence is not part of the name.
+        let username = rest[..end].trim_end_matches('.');
+
+        if !username.is_empty() && !mentions.iter().any(|mention| mention == username) {
+          mentions.push(username.to_string());
+
+          if mentions.len() == MAX_MENTIONS {
+            break;
+          }
+        }
+      }
+
+      prev = Some(c);
+    }
+
+    mentions
+  }
+
+  /// A short plain text rendering of the content.
+  pub fn preview(&self) -> String {
+    let text = match self {
+      Self::Text(text) => text.text.clone(),
+      Self::Code(code) => code.code.clone(),
+      Self::Quote(quote) => format!("> {}", quote.text),
+      Self::Location(location) => match &location.name {
+        Some(name) => name.clone(),
+        None => format!("{}, {}", location.latitude, location.longitude),
+      },
+      Self::File(file) => file.name.clone(),
+      Self::Image(_) => "Image".to_string(),
+      Self::System(SystemMsg::Joined { user }) => format!("User `{user}` joined the room"),
+      Self::System(SystemMsg::Renamed { user, name }) => format!("User `{user}` renamed the room to `{name}`"),
+      Self::System(SystemMsg::Left { user }) => format!("User `{user}` left the room"),
+      Self::System(SystemMsg::Kicked { user, by }) => format!("User `{user}` was kicked by user `{by}`"),
+      Self::System(SystemMsg::Banned { user, by }) => format!("User `{user}` was banned by user `{by}`"),
+    };
+
+    match text.char_indices().nth(PREVIEW_LEN) {
+      Some((end, _)) => format!("{}…", &text[..end]),
+      None => text,
+    }
+  }
+}
+
+/// The message replied to, as shown along with the reply.
+#[derive(Clone, Debug, Serialize)]
+pub struct ReplyPreview {
+  pub uuid: Uuid,
+  pub sender: i32,
+  /// `None` once the message is deleted.
+  pub preview: Option<String>,
+}
+
+/// Everyone who reacted to a message with the same emoji.
+#[derive(Clone, Debug, Serialize)]
+pub struct ReactionCount {
+  pub emoji: String,
+  pub count: usize,
+  pub users: Vec<i32>,
 }
 
 #[derive(Clone, Debug, Serialize)]
@@ -18,7 +200,156 @@ pub struct Msg {
   pub uuid: Uuid,
   pub sender: i32,
   pub room: i32,
-  pub data: MsgContent,
+  /// `None` once the message is deleted.
+  pub data: Option<MsgContent>,
   pub sent: DateTime<Local>,
   pub modified: bool,
+  pub edited: Option<DateTime<Local>>,
+  pub deleted: Option<DateTime<Local>>,
+  pub reply_to: Option<Uuid>,
+  /// The top-level message of the thread this message is posted in.
+  pub thread_root: Option<Uuid>,
+  /// In the order the emojis were first used.
+  pub reactions: Vec<ReactionCount>,
+  pub reply_preview: Option<ReplyPreview>,
+  /// How many messages are posted in the thread started by this message.
+  pub thread_replies: u64,
+}
+
+impl Msg {
+  /// A new message posted by the server on behalf of `sender`.
+  pub fn system(sender: i32, room: i32, data: SystemMsg) -> Self {
+    Self {
+      uuid: Uuid::new_v4(),
+      sender,
+      room,
+      data: Some(MsgContent::System(data)),
+      sent: Local::now(),
+      modified: false,
+      edited: None,
+      deleted: None,
+      reply_to: None,
+      thread_root: None,
+      reactions: vec![],
+      reply_preview: None,
+      thread_replies: 0,
+    }
+  }
+
+  pub fn from_model(model: message::Model) -> Result<Self> {
+    Ok(Self {
+      uuid: model.uuid,
+      sender: model.sender,
+      room: model.room,
+      data: serde_json::from_str(&model.data)?,
+      sent: model.sent,
+      modified: model.modified,
+      edited: model.edited,
+      deleted: model.deleted,
+      reply_to: model.reply_to,
+      thread_root: model.thread_root,
+      reactions: vec![],
+      reply_preview: None,
+      thread_replies: 0,
+    })
+  }
+
+  pub fn to_active_model(&self) -> Result<message::ActiveModel> {
+    Ok(message::ActiveModel {
+      uuid: ActiveValue::Set(self.uuid),
+      sender: ActiveValue::Set(self.sender),
+      room: ActiveValue::Set(self.room),
+      data: ActiveValue::Set(serde_json::to_string(&self.data)?),
+      sent: ActiveValue::Set(self.sent),
+      modified: ActiveValue::Set(self.modified),
+      edited: ActiveValue::Set(self.edited),
+      deleted: ActiveValue::Set(self.deleted),
+      reply_to: ActiveValue::Set(self.reply_to),
+      thread_root: ActiveValue::Set(self.thread_root),
+    })
+  }
+}
+
+/// A previous content of an edited message.
+#[derive(Clone, Debug, Serialize)]
+pub struct Revision {
+  pub data: MsgContent,
+  pub written: DateTime<Local>,
+  pub replaced: DateTime<Local>,
+}
+
+impl Revision {
+  pub fn from_model(model: message_revision::Model) -> Result<Self> {
+    Ok(Self {
+      data: serde_json::from_str(&model.data)?,
+      written: model.written,
+      replaced: model.replaced,
+    })
+  }
+}
+
+/// A message matching a search, with the matching part highlighted.
+#[derive(Clone, Debug, Serialize)]
+pub struct SearchHit {
+  pub msg: Msg,
+  /// The matching text around the match, escaped for HTML, with matched
+  /// terms wrapped in `<mark>` and `</mark>`.
+  pub snippet: String,
+}
+
+/// A message mentioning the user.
+#[derive(Clone, Debug, Serialize)]
+pub struct MentionHit {
+  pub msg: Msg,
+  pub mentioned: DateTime<Local>,
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn text(text: &str) -> MsgContent {
+    MsgContent::Text(TextMsg { text: text.to_string() })
+  }
+
+  #[test]
+  fn finds_mentions_at_the_edges() {
+    assert_eq!(text("@alice look").mentions(), ["alice"]);
+    assert_eq!(text("look @alice").mentions(), ["alice"]);
+    assert_eq!(text("@alice").mentions(), ["alice"]);
+  }
+
+  #[test]
+  fn finds_mentions_next_to_punctuation() {
+    assert_eq!(text("(@alice), @bob! @carol: @dave? @erin.").mentions(), ["alice", "bob", "carol", "dave", "erin"]);
+    assert_eq!(text("@j.doe.").mentions(), ["j.doe"]);
+    assert!(text("@ @. @!").mentions().is_empty());
+  }
+
+  #[test]
+  fn keeps_mentions_once() {
+    assert_eq!(text("@alice @bob @alice, @alice").mentions(), ["alice", "bob"]);
+  }
+
+  #[test]
+  fn ignores_email_addresses() {
+    assert!(text("write to a@b.c or alice@example.com").mentions().is_empty());
+    assert_eq!(text("alice@example.com cc @bob").mentions(), ["bob"]);
+  }
+
+  #[test]
+  fn ignores_code_and_quotes() {
+    let code = MsgContent::Code(CodeMsg { language: None, code: "@alice".to_string() });
+    let quote = MsgContent::Quote(QuoteMsg { text: "@alice".to_string(), source: Some("@bob".to_string()) });
+
+    assert!(code.mentions().is_empty());
+    assert!(quote.mentions().is_empty());
+  }
+
+  #[test]
+  fn limits_mentions() {
+    let many: Vec<String> = (0..MAX_MENTIONS + 5).map(|i| format!("@user{i}")).collect();
+
+    assert_eq!(text(&many.join(" ")).mentions().len(), MAX_MENTIONS);
+  }
 }
@@ -0,0 +1,54 @@
+use std::{sync::Arc, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use crate::AppState;
+
+/// How often the presence of online users is checked for going idle.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Whether a user is connected, computed from their WebSocket connections.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum Presence {
+  Online,
+  Idle,
+  Offline,
+}
+
+/// What a user chose to show alongside their presence.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Availability {
+  Available,
+  Away,
+  DoNotDisturb,
+}
+
+impl Availability {
+  pub fn from_i32(value: i32) -> Self {
+    match value {
+      1 => Self::Away,
+      2 => Self::DoNotDisturb,
+      _ => Self::Available,
+    }
+  }
+
+  pub fn to_i32(self) -> i32 {
+    match self {
+      Self::Available => 0,
+      Self::Away => 1,
+      Self::DoNotDisturb => 2,
+    }
+  }
+}
+
+/// Periodically tells room members about users going idle.
+pub async fn sweep(state: Arc<AppState>) {
+  let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+
+  loop {
+    interval.tick().await;
+
+    state.registry.lock().unwrap()
+      .refresh_all_presence(state.config.idle_after);
+  }
+}
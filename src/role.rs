@@ -0,0 +1,84 @@
+use serde::{Deserialize, Serialize};
+
+/// What a member may do in a room, in ascending order of rank.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Role {
+  Member,
+  Moderator,
+  Admin,
+  Owner,
+}
+
+/// Something in a room only some members may do.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Permission {
+  /// Delete messages sent by others.
+  DeleteMsgs,
+  /// Change the roles of members ranked below.
+  ManageRoles,
+  /// Create invites to the room.
+  Invite,
+  /// Kick and ban members ranked below.
+  Kick,
+  /// Change the name, description, avatar and topic of the room.
+  EditRoom,
+  /// Archive the room for everyone.
+  ArchiveRoom,
+}
+
+/// Who can find and join a room.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Visibility {
+  /// Listed to everyone, and anyone may join.
+  Public,
+  /// Hidden from those outside, who can only join with an invite.
+  InviteOnly,
+  /// A conversation between two users, which no one else can join.
+  Direct,
+}
+
+impl Visibility {
+  pub fn from_i32(value: i32) -> Self {
+    match value {
+      1 => Self::InviteOnly,
+      2 => Self::Direct,
+      _ => Self::Public,
+    }
+  }
+
+  pub fn to_i32(self) -> i32 {
+    match self {
+      Self::Public => 0,
+      Self::InviteOnly => 1,
+      Self::Direct => 2,
+    }
+  }
+}
+
+impl Role {
+  pub fn from_i32(value: i32) -> Self {
+    match value {
+      1 => Self::Moderator,
+      2 => Self::Admin,
+      3 => Self::Owner,
+      _ => Self::Member,
+    }
+  }
+
+  pub fn to_i32(self) -> i32 {
+    match self {
+      Self::Member => 0,
+      Self::Moderator => 1,
+      Self::Admin => 2,
+      Self::Owner => 3,
+    }
+  }
+
+  pub fn can(self, permission: Permission) -> bool {
+    match permission {
+      Permission::DeleteMsgs | Permission::Invite | Permission::Kick => self >= Self::Moderator,
+      Permission::ManageRoles | Permission::EditRoom => self >= Self::Admin,
+      Permission::ArchiveRoom => self == Self::Owner,
+    }
+  }
+}
@@ -0,0 +1,144 @@
+use std::sync::Arc;
+
+use serde::Deserialize;
+use axum::{extract::{State, Path}, http::StatusCode, Json};
+use sea_orm::EntityTrait;
+
+use crate::{AppState, entities::{prelude::*, room, user}, utils::{auth, self}};
+
+use super::{ErrOr, Resp, room::{MyRoom, load_my_room}};
+
+#[derive(Deserialize)]
+pub struct DmPayload {
+  token: String,
+}
+
+/// Gets the room the user talks privately with `peer` in, starting it if needed.
+pub async fn get_dm(
+  State(state): State<Arc<AppState>>,
+  Path(peer): Path<i32>,
+  Json(payload): Json<DmPayload>,
+) -> (StatusCode, Json<ErrOr<MyRoom>>) {
+  info!("POST /dms/{peer}");
+
+  let user = match auth(&state.db, &payload.token).await {
+    Ok(user) => user,
+    Err(err) => {
+      error!("{err}");
+      return (
+        StatusCode::UNAUTHORIZED,
+        Json(ErrOr::Err(Resp { code: 1, msg: err.to_string() })),
+      );
+    },
+  };
+
+  if peer == user.id {
+    info!("User `{peer}` cannot message themselves!");
+    return (
+      StatusCode::BAD_REQUEST,
+      Json(ErrOr::Err(Resp { code: 3, msg: "You cannot message yourself!".to_string() })),
+    );
+  }
+
+  match utils::get_dm_room(&state.db, user.id, peer).await {
+    Err(err) => {
+      error!("{err}");
+      return (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(ErrOr::Err(Resp { code: 2, msg: "Error accessing database!".to_string() })),
+      );
+    },
+    Ok(Some(room)) => return respond(&state, &user, room, StatusCode::OK).await,
+    Ok(None) => (),
+  }
+
+  let peer = match User::find_by_id(peer).one(&state.db).await {
+    Err(err) => {
+      error!("{err}");
+      return (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(ErrOr::Err(Resp { code: 2, msg: "Error accessing database!".to_string() })),
+      );
+    },
+    Ok(None) => {
+      info!("The user `{peer}` does not exist!");
+      return (
+        StatusCode::BAD_REQUEST,
+        Json(ErrOr::Err(Resp { code: 4, msg: format!("The user `{peer}` does not exist!") })),
+      );
+    },
+    Ok(Some(peer)) => peer,
+  };
+
+  let room = match utils::new_dm_room(&state.db, &user, &peer).await {
+    Ok(Some(room)) => room,
+    // Started by the other side in the meantime.
+    Ok(None) => match utils::get_dm_room(&state.db, user.id, peer.id).await {
+      Ok(Some(room)) => return respond(&state, &user, room, StatusCode::OK).await,
+      Ok(None) => {
+        error!("The direct messages of users `{}` and `{}` are missing!", user.id, peer.id);
+        return (
+          StatusCode::INTERNAL_SERVER_ERROR,
+          Json(ErrOr::Err(Resp { code: 5, msg: "Failed to start the direct messages!".to_string() })),
+        );
+      },
+      Err(err) => {
+        error!("{err}");
+        return (
+          StatusCode::INTERNAL_SERVER_ERROR,
+          Json(ErrOr::Err(Resp { code: 2, msg: "Error accessing database!".to_string() })),
+        );
+      },
+    },
+    Err(err) => {
+      error!("{err}");
+      return (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(ErrOr::Err(Resp { code: 5, msg: "Failed to start the direct messages!".to_string() })),
+      );
+    },
+  };
+
+  info!("Users `{}` and `{}` started direct messages in the room `{}`", user.id, peer.id, room.id);
+
+  {
+    let mut registry = state.registry.lock().unwrap();
+
+    registry.join(user.id, room.id);
+    registry.join(peer.id, room.id);
+  }
+
+  respond(&state, &user, room, StatusCode::CREATED).await
+}
+
+/// Shows the direct messages in `room` the way `GET /rooms/me` does, named after the other user.
+async fn respond(
+  state: &AppState,
+  user: &user::Model,
+  room: room::Model,
+  status: StatusCode,
+) -> (StatusCode, Json<ErrOr<MyRoom>>) {
+  let my_room = match utils::get_member(&state.db, user.id, room.id).await {
+    Ok(Some(member)) => load_my_room(state, member).await,
+    Ok(None) => Ok(None),
+    Err(err) => Err(err),
+  };
+
+  match my_room {
+    Ok(Some(my_room)) => (status, Json(ErrOr::Res(my_room))),
+    Ok(None) => {
+      error!("User `{}` is missing from the direct messages `{}`!", user.id, room.id);
+      (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(ErrOr::Err(Resp { code: 5, msg: "Failed to start the direct messages!".to_string() })),
+      )
+    },
+    Err(err) => {
+      error!("{err}");
+      (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(ErrOr::Err(Resp { code: 2, msg: "Error accessing database!".to_string() })),
+      )
+    },
+  }
+}
@@ -0,0 +1,226 @@
+use std::sync::Arc;
+
+use chrono::{Local, Duration};
+use rand::{Rng, distributions::Alphanumeric};
+use serde::Deserialize;
+use axum::{extract::{State, Path}, http::StatusCode, Json};
+use sea_orm::ActiveValue;
+
+use crate::{AppState, entities::{invite, room}, utils::{auth, self}, msg::SystemMsg, role::Permission};
+
+use super::{ErrOr, Resp, room::post_system_msg};
+
+const CODE_LEN: usize = 12;
+
+#[derive(Deserialize)]
+pub struct NewInvitePayload {
+  token: String,
+  /// How long the invite lasts, in seconds; forever if not given.
+  expires_in: Option<i64>,
+  /// How many times the invite can be accepted; unlimited if not given.
+  max_uses: Option<i32>,
+}
+
+pub async fn new_invite(
+  State(state): State<Arc<AppState>>,
+  Path(id): Path<i32>,
+  Json(payload): Json<NewInvitePayload>,
+) -> (StatusCode, Json<ErrOr<invite::Model>>) {
+  info!("POST /rooms/{id}/invites");
+
+  let user = match auth(&state.db, &payload.token).await {
+    Ok(user) => user,
+    Err(err) => {
+      error!("{err}");
+      return (
+        StatusCode::UNAUTHORIZED,
+        Json(ErrOr::Err(Resp { code: 1, msg: err.to_string() })),
+      );
+    },
+  };
+
+  match utils::user_can(&state.db, user.id, id, Permission::Invite).await {
+    Err(err) => {
+      error!("{err}");
+      return (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(ErrOr::Err(Resp { code: 2, msg: "Error accessing database!".to_string() })),
+      );
+    },
+    Ok(false) => {
+      info!("User `{}` cannot invite to the room `{id}`!", user.id);
+      return (
+        StatusCode::FORBIDDEN,
+        Json(ErrOr::Err(Resp { code: 3, msg: format!("You cannot invite to the room `{id}`!") })),
+      );
+    },
+    Ok(true) => (),
+  }
+
+  if payload.expires_in.is_some_and(|secs| secs <= 0) || payload.max_uses.is_some_and(|uses| uses <= 0) {
+    info!("Invalid invite limits!");
+    return (
+      StatusCode::BAD_REQUEST,
+      Json(ErrOr::Err(Resp { code: 4, msg: "`expires_in` and `max_uses` must be positive!".to_string() })),
+    );
+  }
+
+  let now = Local::now();
+
+  let code = rand::thread_rng()
+    .sample_iter(&Alphanumeric)
+    .take(CODE_LEN)
+    .map(char::from)
+    .collect();
+
+  let invite = invite::ActiveModel {
+    code: ActiveValue::Set(code),
+    room: ActiveValue::Set(id),
+    creator: ActiveValue::Set(user.id),
+    created: ActiveValue::Set(now),
+    expires: ActiveValue::Set(payload.expires_in.map(|secs| now + Duration::seconds(secs))),
+    max_uses: ActiveValue::Set(payload.max_uses),
+    uses: ActiveValue::Set(0),
+  };
+
+  match utils::add_invite(&state.db, invite).await {
+    Err(err) => {
+      error!("{err}");
+      (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(ErrOr::Err(Resp { code: 5, msg: "Failed to insert the invite into the database!".to_string() })),
+      )
+    },
+    Ok(invite) => {
+      info!("User `{}` created an invite to the room `{id}`", user.id);
+      (StatusCode::CREATED, Json(ErrOr::Res(invite)))
+    },
+  }
+}
+
+#[derive(Deserialize)]
+pub struct AcceptInvitePayload {
+  token: String,
+}
+
+pub async fn accept_invite(
+  State(state): State<Arc<AppState>>,
+  Path(code): Path<String>,
+  Json(payload): Json<AcceptInvitePayload>,
+) -> (StatusCode, Json<ErrOr<room::Model>>) {
+  info!("POST /invites/{code}/accept");
+
+  let user = match auth(&state.db, &payload.token).await {
+    Ok(user) => user,
+    Err(err) => {
+      error!("{err}");
+      return (
+        StatusCode::UNAUTHORIZED,
+        Json(ErrOr::Err(Resp { code: 1, msg: err.to_string() })),
+      );
+    },
+  };
+
+  let invite = match utils::get_invite(&state.db, &code).await {
+    Err(err) => {
+      error!("{err}");
+      return (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(ErrOr::Err(Resp { code: 2, msg: "Error accessing database!".to_string() })),
+      );
+    },
+    Ok(None) => {
+      info!("The invite `{code}` does not exist!");
+      return (
+        StatusCode::NOT_FOUND,
+        Json(ErrOr::Err(Resp { code: 3, msg: format!("The invite `{code}` does not exist!") })),
+      );
+    },
+    Ok(Some(invite)) => invite,
+  };
+
+  if invite.expires.is_some_and(|expires| expires < Local::now()) {
+    info!("The invite `{code}` has expired!");
+    return (
+      StatusCode::BAD_REQUEST,
+      Json(ErrOr::Err(Resp { code: 4, msg: format!("The invite `{code}` has expired!") })),
+    );
+  }
+
+  let room = match utils::get_room(&state.db, invite.room).await {
+    Err(err) => {
+      error!("{err}");
+      return (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(ErrOr::Err(Resp { code: 2, msg: "Error accessing database!".to_string() })),
+      );
+    },
+    Ok(None) => {
+      info!("The room of the invite `{code}` does not exist!");
+      return (
+        StatusCode::NOT_FOUND,
+        Json(ErrOr::Err(Resp { code: 3, msg: format!("The invite `{code}` does not exist!") })),
+      );
+    },
+    Ok(Some(room)) => room,
+  };
+
+  match utils::user_in_room(&state.db, user.id, room.id).await {
+    Err(err) => {
+      error!("{err}");
+      return (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(ErrOr::Err(Resp { code: 2, msg: "Error accessing database!".to_string() })),
+      );
+    },
+    Ok(true) => {
+      info!("User `{}` is already in the room `{}`!", user.id, room.id);
+      return (
+        StatusCode::BAD_REQUEST,
+        Json(ErrOr::Err(Resp { code: 5, msg: format!("You are already in the room `{}`!", room.id) })),
+      );
+    },
+    Ok(false) => (),
+  }
+
+  match utils::is_banned(&state.db, user.id, room.id).await {
+    Err(err) => {
+      error!("{err}");
+      return (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(ErrOr::Err(Resp { code: 2, msg: "Error accessing database!".to_string() })),
+      );
+    },
+    Ok(true) => {
+      info!("User `{}` is banned from the room `{}`!", user.id, room.id);
+      return (
+        StatusCode::FORBIDDEN,
+        Json(ErrOr::Err(Resp { code: 7, msg: format!("You are banned from the room `{}`!", room.id) })),
+      );
+    },
+    Ok(false) => (),
+  }
+
+  match utils::accept_invite(&state.db, &user, &room, &invite).await {
+    Err(err) => {
+      error!("{err}");
+      (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(ErrOr::Err(Resp { code: 6, msg: format!("Failed to join the room `{}`!", room.id) })),
+      )
+    },
+    Ok(false) => {
+      info!("The invite `{code}` has been used up!");
+      (
+        StatusCode::BAD_REQUEST,
+        Json(ErrOr::Err(Resp { code: 4, msg: format!("The invite `{code}` has been used up!") })),
+      )
+    },
+    Ok(true) => {
+      info!("User `{}` joined the room `{}` with the invite `{code}`", user.id, room.id);
+      state.registry.lock().unwrap().join(user.id, room.id);
+      post_system_msg(&state, user.id, room.id, SystemMsg::Joined { user: user.id }).await;
+      (StatusCode::CREATED, Json(ErrOr::Res(room)))
+    },
+  }
+}
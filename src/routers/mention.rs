@@ -0,0 +1,86 @@
+use std::sync::Arc;
+
+use axum::{
+  extract::{State, Query},
+  http::StatusCode,
+  Json,
+  TypedHeader,
+  headers::{Authorization, authorization::Bearer},
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{AppState, utils::{auth, self}, msg::MentionHit};
+
+use super::{ErrOr, Resp};
+
+const DEFAULT_PAGE_SIZE: u64 = 20;
+const MAX_PAGE_SIZE: u64 = 100;
+
+#[derive(Deserialize)]
+pub struct MentionsQuery {
+  /// The message of the last mention on the previous page.
+  before: Option<Uuid>,
+  limit: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct MentionsResp {
+  mentions: Vec<MentionHit>,
+  more: bool,
+}
+
+pub async fn get_mentions(
+  State(state): State<Arc<AppState>>,
+  Query(query): Query<MentionsQuery>,
+  TypedHeader(token): TypedHeader<Authorization<Bearer>>,
+) -> (StatusCode, Json<ErrOr<MentionsResp>>) {
+  info!("GET /mentions");
+
+  let user = match auth(&state.db, token.token()).await {
+    Ok(user) => user,
+    Err(err) => {
+      error!("{err}");
+      return (
+        StatusCode::UNAUTHORIZED,
+        Json(ErrOr::Err(Resp { code: 1, msg: err.to_string() })),
+      );
+    },
+  };
+
+  let before = match query.before {
+    None => None,
+    Some(uuid) => match utils::get_mention(&state.db, user.id, uuid).await {
+      Err(err) => {
+        error!("{err}");
+        return (
+          StatusCode::INTERNAL_SERVER_ERROR,
+          Json(ErrOr::Err(Resp { code: 2, msg: "Error accessing database!".to_string() })),
+        );
+      },
+      Ok(Some(mention)) => Some(mention),
+      Ok(None) => {
+        info!("The message `{uuid}` does not mention user `{}`!", user.id);
+        return (
+          StatusCode::BAD_REQUEST,
+          Json(ErrOr::Err(Resp { code: 3, msg: format!("The message `{uuid}` does not mention you!") })),
+        );
+      },
+    },
+  };
+
+  let limit = query.limit
+    .unwrap_or(DEFAULT_PAGE_SIZE)
+    .clamp(1, MAX_PAGE_SIZE);
+
+  match utils::get_mentions(&state.db, user.id, before.as_ref(), limit).await {
+    Err(err) => {
+      error!("{err}");
+      (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(ErrOr::Err(Resp { code: 4, msg: "Failed to get mentions from the database!".to_string() })),
+      )
+    },
+    Ok((mentions, more)) => (StatusCode::OK, Json(ErrOr::Res(MentionsResp { mentions, more }))),
+  }
+}
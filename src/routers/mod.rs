@@ -1,11 +1,29 @@
 mod user;
 mod session;
 mod room;
+mod upload;
+mod search;
+mod mention;
+mod invite;
+mod dm;
 
 use serde::Serialize;
 
-pub use user::{login, register, get_user_list, get_user};
+pub use user::{
+  login,
+  register,
+  get_user_list,
+  get_user,
+  get_presence,
+  get_presence_list,
+  set_availability,
+};
 pub use session::get_session_list;
+pub use upload::{upload_file, download_file, download_thumbnail};
+pub use search::search_msgs;
+pub use mention::get_mentions;
+pub use invite::{new_invite, accept_invite};
+pub use dm::get_dm;
 pub use room::{
   new_room,
   get_room_list,
@@ -13,6 +31,18 @@ pub use room::{
   join_room,
   get_room,
   get_my_room,
+  get_room_msgs,
+  get_thread_msgs,
+  get_msg_revisions,
+  delete_msg,
+  mark_read,
+  set_member_role,
+  leave_room,
+  kick_member,
+  get_ban_list,
+  unban_user,
+  edit_room,
+  archive_room,
 };
 
 #[derive(Serialize)]
@@ -1,9 +1,9 @@
 use std::sync::{Arc, Mutex};
 
 use chrono::Local;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use axum::{
-  extract::{State, Path},
+  extract::{State, Path, Query},
   http::StatusCode,
   Json,
   TypedHeader,
@@ -11,15 +11,17 @@ use axum::{
 };
 use sea_orm::{EntityTrait, ActiveValue, QueryFilter, ColumnTrait};
 use tokio::task::JoinHandle;
+use uuid::Uuid;
 
-use crate::{AppState, entities::{prelude::*, room, member}, utils::{auth, self}};
+use crate::{AppState, entities::{prelude::*, room, member, ban}, utils::{auth, self, Cursor}, msg::{Msg, Revision, SystemMsg}, channel::ChannelEvent, role::{Role, Permission, Visibility}};
 
-use super::{ErrOr, Resp};
+use super::{ErrOr, Resp, user::UserWithoutPasswd};
 
 #[derive(Deserialize)]
 pub struct NewRoomPayload {
   token: String,
   name: String,
+  visibility: Option<Visibility>,
 }
 
 #[derive(Serialize)]
@@ -44,10 +46,24 @@ pub async fn new_room(
     },
   };
 
+  let visibility = payload.visibility.unwrap_or(Visibility::Public);
+
+  if visibility == Visibility::Direct {
+    info!("Direct messages cannot be created as a room!");
+    return (
+      StatusCode::BAD_REQUEST,
+      Json(ErrOr::Err(Resp { code: 4, msg: "Start direct messages with `POST /dms/:user_id` instead!".to_string() })),
+    );
+  }
+
   let new_room = room::ActiveModel {
     name: ActiveValue::Set(payload.name),
     description: ActiveValue::Set(String::new()),
     created: ActiveValue::Set(Local::now()),
+    visibility: ActiveValue::Set(visibility.to_i32()),
+    avatar: ActiveValue::Set(None),
+    topic: ActiveValue::Set(String::new()),
+    archived: ActiveValue::Set(None),
     ..Default::default()
   };
 
@@ -66,7 +82,7 @@ pub async fn new_room(
     .one(&state.db).await.unwrap()
     .unwrap();
 
-  match utils::join_room(&state.db, &user, &room).await {
+  match utils::join_room(&state.db, &user, &room, Role::Owner).await {
     Err(_) => {
       error!("Failed to join the new room!");
       (
@@ -76,6 +92,7 @@ pub async fn new_room(
     },
     Ok(_) => {
       info!("New room created: `{room_id}`");
+      state.registry.lock().unwrap().join(user.id, room_id);
       (
         StatusCode::CREATED,
         Json(ErrOr::Res(NewRoomResp { id: room_id })),
@@ -89,7 +106,10 @@ pub async fn get_room_list(
 ) -> (StatusCode, Json<Vec<room::Model>>) {
   warn!("GET /rooms");
 
-  let rooms = Room::find().all(&state.db).await;
+  let rooms = Room::find()
+    .filter(room::Column::Visibility.eq(Visibility::Public.to_i32()))
+    .filter(room::Column::Archived.is_null())
+    .all(&state.db).await;
 
   match rooms {
     Ok(rooms) => (StatusCode::OK, Json(rooms)),
@@ -97,12 +117,25 @@ pub async fn get_room_list(
   }
 }
 
+/// Lists the members of public rooms, and of the rooms of the caller if they give a token.
 pub async fn get_member_list(
   State(state): State<Arc<AppState>>,
+  token: Option<TypedHeader<Authorization<Bearer>>>,
 ) -> (StatusCode, Json<Vec<member::Model>>) {
   warn!("GET /members");
 
-  let members = Member::find().all(&state.db).await;
+  let user = match token {
+    None => None,
+    Some(TypedHeader(token)) => match auth(&state.db, token.token()).await {
+      Ok(user) => Some(user.id),
+      Err(err) => {
+        error!("{err}");
+        return (StatusCode::UNAUTHORIZED, Json(vec![]));
+      },
+    },
+  };
+
+  let members = utils::get_visible_members(&state.db, user).await;
 
   match members {
     Ok(members) => (StatusCode::OK, Json(members)),
@@ -133,8 +166,7 @@ pub async fn join_room(
     },
   };
 
-  let room = Room::find_by_id(id)
-    .one(&state.db).await;
+  let room = utils::get_room(&state.db, id).await;
 
   if let Err(err) = room {
     error!("{err}");
@@ -156,7 +188,43 @@ pub async fn join_room(
 
   let room = room.unwrap();
 
-  match utils::join_room(&state.db, &user, &room).await {
+  match Visibility::from_i32(room.visibility) {
+    Visibility::Public => (),
+    Visibility::InviteOnly => {
+      info!("User `{}` cannot join the room `{id}` without an invite!", user.id);
+      return (
+        StatusCode::FORBIDDEN,
+        Json(Resp { code: 5, msg: format!("The room `{id}` can only be joined with an invite!") }),
+      );
+    },
+    Visibility::Direct => {
+      info!("User `{}` cannot join the direct messages `{id}`!", user.id);
+      return (
+        StatusCode::FORBIDDEN,
+        Json(Resp { code: 5, msg: format!("The room `{id}` cannot be joined!") }),
+      );
+    },
+  }
+
+  match utils::is_banned(&state.db, user.id, id).await {
+    Err(err) => {
+      error!("{err}");
+      return (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(Resp { code: 2, msg: "Failed to get the room from the database!".to_string() }),
+      );
+    },
+    Ok(true) => {
+      info!("User `{}` is banned from the room `{id}`!", user.id);
+      return (
+        StatusCode::FORBIDDEN,
+        Json(Resp { code: 6, msg: format!("You are banned from the room `{id}`!") }),
+      );
+    },
+    Ok(false) => (),
+  }
+
+  match utils::join_room(&state.db, &user, &room, Role::Member).await {
     Err(_) => {
       error!("Failed to join the room `{id}`!");
       (
@@ -166,6 +234,8 @@ pub async fn join_room(
     },
     Ok(_) => {
       info!("User `{}` joined the room `{}`", user.id, room.id);
+      state.registry.lock().unwrap().join(user.id, room.id);
+      post_system_msg(&state, user.id, room.id, SystemMsg::Joined { user: user.id }).await;
       (
         StatusCode::CREATED,
         Json(Resp { code: 0, msg: String::new() }),
@@ -174,14 +244,23 @@ pub async fn join_room(
   }
 }
 
+/// Posts and broadcasts a system message, which is not worth failing the request for.
+pub(super) async fn post_system_msg(state: &AppState, sender: i32, room: i32, data: SystemMsg) {
+  match utils::post_system_msg(&state.db, sender, room, data).await {
+    Ok(msg) => state.registry.lock().unwrap()
+      .send_to_room(room, ChannelEvent::new_msg(msg)),
+    Err(err) => error!("Failed to post a system message to the room `{room}`: {err}"),
+  }
+}
+
 pub async fn get_room(
   State(state): State<Arc<AppState>>,
   Path(id): Path<i32>,
+  token: Option<TypedHeader<Authorization<Bearer>>>,
 ) -> (StatusCode, Json<ErrOr<room::Model>>) {
   info!("GET /rooms/{}", id);
 
-  let room = Room::find_by_id(id)
-    .one(&state.db).await;
+  let room = utils::get_room(&state.db, id).await;
 
   if room.is_err() {
     error!("Error accessing database!");
@@ -203,13 +282,76 @@ pub async fn get_room(
 
   let room = room.unwrap();
 
+  if Visibility::from_i32(room.visibility) != Visibility::Public {
+    let member = match token {
+      None => false,
+      Some(TypedHeader(token)) => match auth(&state.db, token.token()).await {
+        Err(_) => false,
+        Ok(user) => matches!(utils::user_in_room(&state.db, user.id, id).await, Ok(true)),
+      },
+    };
+
+    // Looks the same as a missing room, so that those outside cannot probe for it.
+    if !member {
+      info!("The room `{id}` is hidden!");
+      return (
+        StatusCode::BAD_REQUEST,
+        Json(ErrOr::Err(Resp { code: 2, msg: format!("The room `{id}` does not exist!") })),
+      );
+    }
+  }
+
   (StatusCode::OK, Json(ErrOr::Res(room)))
 }
 
+/// A room of the user, with what they have not read in it yet.
+#[derive(Clone, Serialize)]
+pub struct MyRoom {
+  #[serde(flatten)]
+  room: room::Model,
+  role: Role,
+  /// The other user of direct messages, whose name the room goes by.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  peer: Option<UserWithoutPasswd>,
+  last_read: Option<Uuid>,
+  unread: u64,
+  latest: Option<Msg>,
+}
+
+/// Loads the room of `member` as they see it, `None` if it has been archived.
+pub(super) async fn load_my_room(state: &AppState, member: member::Model) -> anyhow::Result<Option<MyRoom>> {
+  let Some(mut room) = utils::get_room(&state.db, member.room).await? else {
+    return Ok(None);
+  };
+
+  let peer = if Visibility::from_i32(room.visibility) == Visibility::Direct {
+    utils::get_dm_peer(&state.db, room.id, member.user).await?
+  } else {
+    None
+  };
+
+  if let Some(peer) = &peer {
+    room.name = peer.nickname.clone();
+  }
+
+  let unread = utils::count_unread(&state.db, member.user, member.room, member.last_read).await?;
+
+  let (latest, _) = utils::get_room_msgs(&state.db, member.room, Cursor::Latest, 1).await?;
+
+  Ok(Some(MyRoom {
+    room,
+    role: Role::from_i32(member.role),
+    peer: peer.map(UserWithoutPasswd::new),
+    last_read: member.last_read,
+    unread,
+    latest: latest.into_iter().next(),
+  }))
+}
+
 pub async fn get_my_room(
   State(state): State<Arc<AppState>>,
   TypedHeader(token): TypedHeader<Authorization<Bearer>>
-) -> (StatusCode, Json<Vec<room::Model>>) {
+) -> (StatusCode, Json<Vec<MyRoom>>) {
   let user = auth(&state.db, token.token()).await;
 
   if let Err(err) = user {
@@ -243,24 +385,12 @@ pub async fn get_my_room(
       let state = state.clone();
       let rooms = rooms.clone();
       tokio::task::spawn(async move {
-        let room = Room::find_by_id(member.room)
-          .one(&state.db).await;
-  
-        if let Err(err) = room {
-          error!("{err}");
-          return;
+        match load_my_room(&state, member).await {
+          Ok(Some(room)) => rooms.lock().unwrap().push(room),
+          // Archived rooms are kept out of the list.
+          Ok(None) => (),
+          Err(err) => error!("{err}"),
         }
-  
-        let room = room.unwrap();
-  
-        if room.is_none() {
-          error!("Room `{}` not found!", member.room);
-          return;
-        }
-  
-        let room = room.unwrap();
-  
-        rooms.lock().unwrap().push(room);
       })
     })
     .collect();
@@ -273,3 +403,1042 @@ pub async fn get_my_room(
 
   (StatusCode::OK, Json(rooms))
 }
+
+#[derive(Deserialize)]
+pub struct MarkReadPayload {
+  token: String,
+  uuid: Uuid,
+}
+
+pub async fn mark_read(
+  State(state): State<Arc<AppState>>,
+  Path(id): Path<i32>,
+  Json(payload): Json<MarkReadPayload>,
+) -> (StatusCode, Json<Resp>) {
+  info!("POST /rooms/{id}/read");
+
+  let user = match auth(&state.db, &payload.token).await {
+    Ok(user) => user,
+    Err(err) => {
+      error!("{err}");
+      return (
+        StatusCode::UNAUTHORIZED,
+        Json(Resp { code: 1, msg: err.to_string() }),
+      );
+    },
+  };
+
+  match utils::user_in_room(&state.db, user.id, id).await {
+    Err(err) => {
+      error!("{err}");
+      return (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(Resp { code: 2, msg: "Error accessing database!".to_string() }),
+      );
+    },
+    Ok(false) => {
+      info!("User `{}` is not in the room `{id}`!", user.id);
+      return (
+        StatusCode::FORBIDDEN,
+        Json(Resp { code: 3, msg: format!("You are not in the room `{id}`!") }),
+      );
+    },
+    _ => (),
+  }
+
+  let uuid = payload.uuid;
+
+  let msg = match utils::get_msg(&state.db, uuid).await {
+    Err(err) => {
+      error!("{err}");
+      return (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(Resp { code: 2, msg: "Error accessing database!".to_string() }),
+      );
+    },
+    Ok(Some(msg)) if msg.room == id => msg,
+    Ok(_) => {
+      info!("The message `{uuid}` is not in the room `{id}`!");
+      return (
+        StatusCode::BAD_REQUEST,
+        Json(Resp { code: 4, msg: format!("The message `{uuid}` is not in the room `{id}`!") }),
+      );
+    },
+  };
+
+  match utils::mark_read(&state.db, user.id, &msg).await {
+    Err(err) => {
+      error!("{err}");
+      (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(Resp { code: 5, msg: "Failed to update the read marker!".to_string() }),
+      )
+    },
+    Ok(moved) => {
+      if moved {
+        info!("User `{}` read the room `{id}` up to `{uuid}`", user.id);
+        state.registry.lock().unwrap()
+          .send_to_room(id, ChannelEvent::new_read(user.id, &msg));
+      }
+      (
+        StatusCode::OK,
+        Json(Resp { code: 0, msg: String::new() }),
+      )
+    },
+  }
+}
+
+const DEFAULT_PAGE_SIZE: u64 = 50;
+const MAX_PAGE_SIZE: u64 = 100;
+
+#[derive(Deserialize)]
+pub struct RoomMsgsQuery {
+  before: Option<Uuid>,
+  after: Option<Uuid>,
+  limit: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct RoomMsgsResp {
+  msgs: Vec<Msg>,
+  more: bool,
+}
+
+pub async fn get_room_msgs(
+  State(state): State<Arc<AppState>>,
+  Path(id): Path<i32>,
+  Query(query): Query<RoomMsgsQuery>,
+  TypedHeader(token): TypedHeader<Authorization<Bearer>>,
+) -> (StatusCode, Json<ErrOr<RoomMsgsResp>>) {
+  info!("GET /rooms/{id}/messages");
+
+  let user = match auth(&state.db, token.token()).await {
+    Ok(user) => user,
+    Err(err) => {
+      error!("{err}");
+      return (
+        StatusCode::UNAUTHORIZED,
+        Json(ErrOr::Err(Resp { code: 1, msg: err.to_string() })),
+      );
+    },
+  };
+
+  match utils::user_in_room(&state.db, user.id, id).await {
+    Err(err) => {
+      error!("{err}");
+      return (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(ErrOr::Err(Resp { code: 2, msg: "Error accessing database!".to_string() })),
+      );
+    },
+    Ok(false) => {
+      info!("User `{}` is not in the room `{id}`!", user.id);
+      return (
+        StatusCode::FORBIDDEN,
+        Json(ErrOr::Err(Resp { code: 3, msg: format!("You are not in the room `{id}`!") })),
+      );
+    },
+    _ => (),
+  }
+
+  let cursor_uuid = match (query.before, query.after) {
+    (Some(_), Some(_)) => {
+      info!("Both `before` and `after` are given!");
+      return (
+        StatusCode::BAD_REQUEST,
+        Json(ErrOr::Err(Resp { code: 4, msg: "Only one of `before` and `after` can be given!".to_string() })),
+      );
+    },
+    (Some(uuid), None) | (None, Some(uuid)) => Some(uuid),
+    (None, None) => None,
+  };
+
+  let cursor_msg = match cursor_uuid {
+    None => None,
+    Some(uuid) => match utils::get_msg(&state.db, uuid).await {
+      Err(err) => {
+        error!("{err}");
+        return (
+          StatusCode::INTERNAL_SERVER_ERROR,
+          Json(ErrOr::Err(Resp { code: 2, msg: "Error accessing database!".to_string() })),
+        );
+      },
+      Ok(Some(msg)) if msg.room == id => Some(msg),
+      Ok(_) => {
+        info!("The message `{uuid}` is not in the room `{id}`!");
+        return (
+          StatusCode::BAD_REQUEST,
+          Json(ErrOr::Err(Resp { code: 5, msg: format!("The message `{uuid}` is not in the room `{id}`!") })),
+        );
+      },
+    },
+  };
+
+  let cursor = match (&cursor_msg, query.before.is_some()) {
+    (None, _) => Cursor::Latest,
+    (Some(msg), true) => Cursor::Before(msg),
+    (Some(msg), false) => Cursor::After(msg),
+  };
+
+  let limit = query.limit
+    .unwrap_or(DEFAULT_PAGE_SIZE)
+    .clamp(1, MAX_PAGE_SIZE);
+
+  match utils::get_room_msgs(&state.db, id, cursor, limit).await {
+    Err(err) => {
+      error!("{err}");
+      (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(ErrOr::Err(Resp { code: 6, msg: "Failed to get messages from the database!".to_string() })),
+      )
+    },
+    Ok((msgs, more)) => (StatusCode::OK, Json(ErrOr::Res(RoomMsgsResp { msgs, more }))),
+  }
+}
+
+#[derive(Serialize)]
+pub struct ThreadMsgsResp {
+  root: Msg,
+  msgs: Vec<Msg>,
+  more: bool,
+}
+
+pub async fn get_thread_msgs(
+  State(state): State<Arc<AppState>>,
+  Path((id, uuid)): Path<(i32, Uuid)>,
+  Query(query): Query<RoomMsgsQuery>,
+  TypedHeader(token): TypedHeader<Authorization<Bearer>>,
+) -> (StatusCode, Json<ErrOr<ThreadMsgsResp>>) {
+  info!("GET /rooms/{id}/threads/{uuid}");
+
+  let user = match auth(&state.db, token.token()).await {
+    Ok(user) => user,
+    Err(err) => {
+      error!("{err}");
+      return (
+        StatusCode::UNAUTHORIZED,
+        Json(ErrOr::Err(Resp { code: 1, msg: err.to_string() })),
+      );
+    },
+  };
+
+  match utils::user_in_room(&state.db, user.id, id).await {
+    Err(err) => {
+      error!("{err}");
+      return (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(ErrOr::Err(Resp { code: 2, msg: "Error accessing database!".to_string() })),
+      );
+    },
+    Ok(false) => {
+      info!("User `{}` is not in the room `{id}`!", user.id);
+      return (
+        StatusCode::FORBIDDEN,
+        Json(ErrOr::Err(Resp { code: 3, msg: format!("You are not in the room `{id}`!") })),
+      );
+    },
+    _ => (),
+  }
+
+  let root = match utils::get_msg(&state.db, uuid).await {
+    Err(err) => {
+      error!("{err}");
+      return (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(ErrOr::Err(Resp { code: 2, msg: "Error accessing database!".to_string() })),
+      );
+    },
+    Ok(Some(msg)) if msg.room == id && msg.thread_root.is_none() => msg,
+    Ok(_) => {
+      info!("The message `{uuid}` does not start a thread in the room `{id}`!");
+      return (
+        StatusCode::BAD_REQUEST,
+        Json(ErrOr::Err(Resp { code: 4, msg: format!("The message `{uuid}` does not start a thread in the room `{id}`!") })),
+      );
+    },
+  };
+
+  let cursor_uuid = match (query.before, query.after) {
+    (Some(_), Some(_)) => {
+      info!("Both `before` and `after` are given!");
+      return (
+        StatusCode::BAD_REQUEST,
+        Json(ErrOr::Err(Resp { code: 5, msg: "Only one of `before` and `after` can be given!".to_string() })),
+      );
+    },
+    (Some(uuid), None) | (None, Some(uuid)) => Some(uuid),
+    (None, None) => None,
+  };
+
+  let cursor_msg = match cursor_uuid {
+    None => None,
+    Some(cursor) => match utils::get_msg(&state.db, cursor).await {
+      Err(err) => {
+        error!("{err}");
+        return (
+          StatusCode::INTERNAL_SERVER_ERROR,
+          Json(ErrOr::Err(Resp { code: 2, msg: "Error accessing database!".to_string() })),
+        );
+      },
+      Ok(Some(msg)) if msg.thread_root == Some(uuid) => Some(msg),
+      Ok(_) => {
+        info!("The message `{cursor}` is not in the thread `{uuid}`!");
+        return (
+          StatusCode::BAD_REQUEST,
+          Json(ErrOr::Err(Resp { code: 6, msg: format!("The message `{cursor}` is not in the thread `{uuid}`!") })),
+        );
+      },
+    },
+  };
+
+  let cursor = match (&cursor_msg, query.before.is_some()) {
+    (None, _) => Cursor::Latest,
+    (Some(msg), true) => Cursor::Before(msg),
+    (Some(msg), false) => Cursor::After(msg),
+  };
+
+  let limit = query.limit
+    .unwrap_or(DEFAULT_PAGE_SIZE)
+    .clamp(1, MAX_PAGE_SIZE);
+
+  match utils::get_thread_msgs(&state.db, uuid, cursor, limit).await {
+    Err(err) => {
+      error!("{err}");
+      (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(ErrOr::Err(Resp { code: 7, msg: "Failed to get messages from the database!".to_string() })),
+      )
+    },
+    Ok((msgs, more)) => (StatusCode::OK, Json(ErrOr::Res(ThreadMsgsResp { root, msgs, more }))),
+  }
+}
+
+pub async fn get_msg_revisions(
+  State(state): State<Arc<AppState>>,
+  Path((id, uuid)): Path<(i32, Uuid)>,
+  TypedHeader(token): TypedHeader<Authorization<Bearer>>,
+) -> (StatusCode, Json<ErrOr<Vec<Revision>>>) {
+  info!("GET /rooms/{id}/messages/{uuid}/revisions");
+
+  let user = match auth(&state.db, token.token()).await {
+    Ok(user) => user,
+    Err(err) => {
+      error!("{err}");
+      return (
+        StatusCode::UNAUTHORIZED,
+        Json(ErrOr::Err(Resp { code: 1, msg: err.to_string() })),
+      );
+    },
+  };
+
+  match utils::user_in_room(&state.db, user.id, id).await {
+    Err(err) => {
+      error!("{err}");
+      return (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(ErrOr::Err(Resp { code: 2, msg: "Error accessing database!".to_string() })),
+      );
+    },
+    Ok(false) => {
+      info!("User `{}` is not in the room `{id}`!", user.id);
+      return (
+        StatusCode::FORBIDDEN,
+        Json(ErrOr::Err(Resp { code: 3, msg: format!("You are not in the room `{id}`!") })),
+      );
+    },
+    _ => (),
+  }
+
+  match utils::get_msg(&state.db, uuid).await {
+    Err(err) => {
+      error!("{err}");
+      return (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(ErrOr::Err(Resp { code: 2, msg: "Error accessing database!".to_string() })),
+      );
+    },
+    Ok(Some(msg)) if msg.room == id => (),
+    Ok(_) => {
+      info!("The message `{uuid}` is not in the room `{id}`!");
+      return (
+        StatusCode::BAD_REQUEST,
+        Json(ErrOr::Err(Resp { code: 4, msg: format!("The message `{uuid}` is not in the room `{id}`!") })),
+      );
+    },
+  }
+
+  match utils::get_revisions(&state.db, uuid).await {
+    Err(err) => {
+      error!("{err}");
+      (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(ErrOr::Err(Resp { code: 5, msg: "Failed to get revisions from the database!".to_string() })),
+      )
+    },
+    Ok(revisions) => (StatusCode::OK, Json(ErrOr::Res(revisions))),
+  }
+}
+
+pub async fn delete_msg(
+  State(state): State<Arc<AppState>>,
+  Path((id, uuid)): Path<(i32, Uuid)>,
+  TypedHeader(token): TypedHeader<Authorization<Bearer>>,
+) -> (StatusCode, Json<Resp>) {
+  info!("DELETE /rooms/{id}/messages/{uuid}");
+
+  let user = match auth(&state.db, token.token()).await {
+    Ok(user) => user,
+    Err(err) => {
+      error!("{err}");
+      return (
+        StatusCode::UNAUTHORIZED,
+        Json(Resp { code: 1, msg: err.to_string() }),
+      );
+    },
+  };
+
+  let msg = match utils::get_msg(&state.db, uuid).await {
+    Err(err) => {
+      error!("{err}");
+      return (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(Resp { code: 2, msg: "Error accessing database!".to_string() }),
+      );
+    },
+    Ok(Some(msg)) if msg.room == id => msg,
+    Ok(_) => {
+      info!("The message `{uuid}` is not in the room `{id}`!");
+      return (
+        StatusCode::BAD_REQUEST,
+        Json(Resp { code: 3, msg: format!("The message `{uuid}` is not in the room `{id}`!") }),
+      );
+    },
+  };
+
+  match utils::can_delete_msg(&state.db, user.id, &msg).await {
+    Err(err) => {
+      error!("{err}");
+      return (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(Resp { code: 2, msg: "Error accessing database!".to_string() }),
+      );
+    },
+    Ok(false) => {
+      info!("User `{}` cannot delete the message `{uuid}`!", user.id);
+      return (
+        StatusCode::FORBIDDEN,
+        Json(Resp { code: 4, msg: "You cannot delete this message!".to_string() }),
+      );
+    },
+    Ok(true) => (),
+  }
+
+  if msg.deleted.is_some() {
+    info!("The message `{uuid}` has been deleted!");
+    return (
+      StatusCode::BAD_REQUEST,
+      Json(Resp { code: 5, msg: format!("The message `{uuid}` has been deleted!") }),
+    );
+  }
+
+  match utils::delete_msg(&state.db, msg).await {
+    Err(err) => {
+      error!("{err}");
+      (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(Resp { code: 6, msg: format!("Failed to delete the message `{uuid}`!") }),
+      )
+    },
+    Ok(msg) => {
+      info!("User `{}` deleted the message `{uuid}`", user.id);
+      state.registry.lock().unwrap()
+        .send_to_room(id, ChannelEvent::new_delete(msg));
+      (
+        StatusCode::OK,
+        Json(Resp { code: 0, msg: String::new() }),
+      )
+    },
+  }
+}
+
+/// The roles of `user` acting on `member` in `room`, both of whom must be in it.
+async fn get_member_roles(
+  state: &AppState,
+  room: i32,
+  user: i32,
+  member: i32,
+) -> Result<(Role, Role), (StatusCode, Json<Resp>)> {
+  let roles = match (
+    utils::member_role(&state.db, user, room).await,
+    utils::member_role(&state.db, member, room).await,
+  ) {
+    (Ok(own), Ok(theirs)) => (own, theirs),
+    (Err(err), _) | (_, Err(err)) => {
+      error!("{err}");
+      return Err((
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(Resp { code: 2, msg: "Error accessing database!".to_string() }),
+      ));
+    },
+  };
+
+  match roles {
+    (None, _) => {
+      info!("User `{user}` is not in the room `{room}`!");
+      Err((
+        StatusCode::FORBIDDEN,
+        Json(Resp { code: 3, msg: format!("You are not in the room `{room}`!") }),
+      ))
+    },
+    (_, None) => {
+      info!("User `{member}` is not in the room `{room}`!");
+      Err((
+        StatusCode::BAD_REQUEST,
+        Json(Resp { code: 4, msg: format!("User `{member}` is not in the room `{room}`!") }),
+      ))
+    },
+    (Some(own), Some(theirs)) => Ok((own, theirs)),
+  }
+}
+
+#[derive(Deserialize)]
+pub struct SetRolePayload {
+  token: String,
+  role: Role,
+}
+
+pub async fn set_member_role(
+  State(state): State<Arc<AppState>>,
+  Path((id, member)): Path<(i32, i32)>,
+  Json(payload): Json<SetRolePayload>,
+) -> (StatusCode, Json<Resp>) {
+  info!("POST /rooms/{id}/members/{member}/role");
+
+  let user = match auth(&state.db, &payload.token).await {
+    Ok(user) => user,
+    Err(err) => {
+      error!("{err}");
+      return (
+        StatusCode::UNAUTHORIZED,
+        Json(Resp { code: 1, msg: err.to_string() }),
+      );
+    },
+  };
+
+  let (own, theirs) = match get_member_roles(&state, id, user.id, member).await {
+    Ok(roles) => roles,
+    Err(resp) => return resp,
+  };
+
+  // Only the owner may hand over the ownership, anyone else only manages those ranked below.
+  let allowed = member != user.id
+    && own.can(Permission::ManageRoles)
+    && theirs < own
+    && (payload.role < own || own == Role::Owner);
+
+  if !allowed {
+    info!("User `{}` cannot make user `{member}` {:?}!", user.id, payload.role);
+    return (
+      StatusCode::FORBIDDEN,
+      Json(Resp { code: 5, msg: format!("You cannot make user `{member}` {:?}!", payload.role) }),
+    );
+  }
+
+  let previous_owner = (payload.role == Role::Owner).then_some(user.id);
+
+  match utils::set_role(&state.db, id, member, payload.role, previous_owner).await {
+    Err(err) => {
+      error!("{err}");
+      (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(Resp { code: 6, msg: "Failed to change the role!".to_string() }),
+      )
+    },
+    Ok(_) => {
+      info!("User `{}` made user `{member}` {:?} in the room `{id}`", user.id, payload.role);
+
+      let mut registry = state.registry.lock().unwrap();
+
+      registry.send_to_room(id, ChannelEvent::new_role(id, member, payload.role));
+
+      if let Some(owner) = previous_owner {
+        registry.send_to_room(id, ChannelEvent::new_role(id, owner, Role::Admin));
+      }
+
+      (
+        StatusCode::OK,
+        Json(Resp { code: 0, msg: String::new() }),
+      )
+    },
+  }
+}
+
+#[derive(Deserialize)]
+pub struct LeaveRoomPayload {
+  token: String,
+}
+
+pub async fn leave_room(
+  State(state): State<Arc<AppState>>,
+  Path(id): Path<i32>,
+  Json(payload): Json<LeaveRoomPayload>,
+) -> (StatusCode, Json<Resp>) {
+  info!("POST /rooms/{id}/leave");
+
+  let user = match auth(&state.db, &payload.token).await {
+    Ok(user) => user,
+    Err(err) => {
+      error!("{err}");
+      return (
+        StatusCode::UNAUTHORIZED,
+        Json(Resp { code: 1, msg: err.to_string() }),
+      );
+    },
+  };
+
+  match utils::member_role(&state.db, user.id, id).await {
+    Err(err) => {
+      error!("{err}");
+      return (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(Resp { code: 2, msg: "Error accessing database!".to_string() }),
+      );
+    },
+    Ok(None) => {
+      info!("User `{}` is not in the room `{id}`!", user.id);
+      return (
+        StatusCode::FORBIDDEN,
+        Json(Resp { code: 3, msg: format!("You are not in the room `{id}`!") }),
+      );
+    },
+    // The room would be left without anyone to manage it.
+    Ok(Some(Role::Owner)) => {
+      info!("The owner cannot leave the room `{id}`!");
+      return (
+        StatusCode::BAD_REQUEST,
+        Json(Resp { code: 4, msg: "Hand over the ownership before leaving the room!".to_string() }),
+      );
+    },
+    Ok(Some(_)) => (),
+  }
+
+  match utils::get_room(&state.db, id).await {
+    Err(err) => {
+      error!("{err}");
+      return (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(Resp { code: 2, msg: "Error accessing database!".to_string() }),
+      );
+    },
+    // The pair could never talk privately again, as the room is kept for them.
+    Ok(Some(room)) if Visibility::from_i32(room.visibility) == Visibility::Direct => {
+      info!("User `{}` cannot leave the direct messages `{id}`!", user.id);
+      return (
+        StatusCode::BAD_REQUEST,
+        Json(Resp { code: 4, msg: "Direct messages cannot be left!".to_string() }),
+      );
+    },
+    Ok(_) => (),
+  }
+
+  match utils::remove_member(&state.db, id, user.id, None).await {
+    Err(err) => {
+      error!("{err}");
+      (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(Resp { code: 5, msg: format!("Failed to leave the room `{id}`!") }),
+      )
+    },
+    Ok(_) => {
+      info!("User `{}` left the room `{id}`", user.id);
+      remove_from_registry(&state, id, user.id, None, false);
+      post_system_msg(&state, user.id, id, SystemMsg::Left { user: user.id }).await;
+      (
+        StatusCode::OK,
+        Json(Resp { code: 0, msg: String::new() }),
+      )
+    },
+  }
+}
+
+/// Tells the room, the removed user included, and stops their connections from receiving it.
+fn remove_from_registry(state: &AppState, room: i32, user: i32, by: Option<i32>, banned: bool) {
+  let mut registry = state.registry.lock().unwrap();
+
+  registry.send_to_room(room, ChannelEvent::new_removed(room, user, by, banned));
+  registry.leave(user, room);
+}
+
+#[derive(Deserialize)]
+pub struct KickPayload {
+  token: String,
+  /// Also keeps the user from joining again.
+  #[serde(default)]
+  ban: bool,
+}
+
+pub async fn kick_member(
+  State(state): State<Arc<AppState>>,
+  Path((id, member)): Path<(i32, i32)>,
+  Json(payload): Json<KickPayload>,
+) -> (StatusCode, Json<Resp>) {
+  info!("POST /rooms/{id}/members/{member}/kick");
+
+  let user = match auth(&state.db, &payload.token).await {
+    Ok(user) => user,
+    Err(err) => {
+      error!("{err}");
+      return (
+        StatusCode::UNAUTHORIZED,
+        Json(Resp { code: 1, msg: err.to_string() }),
+      );
+    },
+  };
+
+  let (own, theirs) = match get_member_roles(&state, id, user.id, member).await {
+    Ok(roles) => roles,
+    Err(resp) => return resp,
+  };
+
+  if member == user.id || !own.can(Permission::Kick) || theirs >= own {
+    info!("User `{}` cannot kick user `{member}`!", user.id);
+    return (
+      StatusCode::FORBIDDEN,
+      Json(Resp { code: 5, msg: format!("You cannot kick user `{member}`!") }),
+    );
+  }
+
+  let banned_by = payload.ban.then_some(user.id);
+
+  match utils::remove_member(&state.db, id, member, banned_by).await {
+    Err(err) => {
+      error!("{err}");
+      (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(Resp { code: 6, msg: format!("Failed to kick user `{member}`!") }),
+      )
+    },
+    Ok(_) => {
+      info!("User `{}` kicked user `{member}` out of the room `{id}`, banned: {}", user.id, payload.ban);
+      remove_from_registry(&state, id, member, Some(user.id), payload.ban);
+
+      let data = if payload.ban {
+        SystemMsg::Banned { user: member, by: user.id }
+      } else {
+        SystemMsg::Kicked { user: member, by: user.id }
+      };
+
+      post_system_msg(&state, user.id, id, data).await;
+      (
+        StatusCode::OK,
+        Json(Resp { code: 0, msg: String::new() }),
+      )
+    },
+  }
+}
+
+/// Checks that the user of `token` may manage the bans of `room`.
+async fn auth_ban_manager(state: &AppState, token: &str, room: i32) -> Result<i32, (StatusCode, Json<Resp>)> {
+  let user = match auth(&state.db, token).await {
+    Ok(user) => user,
+    Err(err) => {
+      error!("{err}");
+      return Err((
+        StatusCode::UNAUTHORIZED,
+        Json(Resp { code: 1, msg: err.to_string() }),
+      ));
+    },
+  };
+
+  match utils::user_can(&state.db, user.id, room, Permission::Kick).await {
+    Err(err) => {
+      error!("{err}");
+      Err((
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(Resp { code: 2, msg: "Error accessing database!".to_string() }),
+      ))
+    },
+    Ok(false) => {
+      info!("User `{}` cannot manage the bans of the room `{room}`!", user.id);
+      Err((
+        StatusCode::FORBIDDEN,
+        Json(Resp { code: 3, msg: format!("You cannot manage the bans of the room `{room}`!") }),
+      ))
+    },
+    Ok(true) => Ok(user.id),
+  }
+}
+
+pub async fn get_ban_list(
+  State(state): State<Arc<AppState>>,
+  Path(id): Path<i32>,
+  TypedHeader(token): TypedHeader<Authorization<Bearer>>,
+) -> (StatusCode, Json<ErrOr<Vec<ban::Model>>>) {
+  info!("GET /rooms/{id}/bans");
+
+  if let Err((status, Json(resp))) = auth_ban_manager(&state, token.token(), id).await {
+    return (status, Json(ErrOr::Err(resp)));
+  }
+
+  match utils::get_bans(&state.db, id).await {
+    Err(err) => {
+      error!("{err}");
+      (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(ErrOr::Err(Resp { code: 2, msg: "Error accessing database!".to_string() })),
+      )
+    },
+    Ok(bans) => (StatusCode::OK, Json(ErrOr::Res(bans))),
+  }
+}
+
+pub async fn unban_user(
+  State(state): State<Arc<AppState>>,
+  Path((id, banned)): Path<(i32, i32)>,
+  TypedHeader(token): TypedHeader<Authorization<Bearer>>,
+) -> (StatusCode, Json<Resp>) {
+  info!("DELETE /rooms/{id}/bans/{banned}");
+
+  let user = match auth_ban_manager(&state, token.token(), id).await {
+    Ok(user) => user,
+    Err(resp) => return resp,
+  };
+
+  match utils::unban(&state.db, id, banned).await {
+    Err(err) => {
+      error!("{err}");
+      (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(Resp { code: 2, msg: "Error accessing database!".to_string() }),
+      )
+    },
+    Ok(false) => {
+      info!("User `{banned}` is not banned from the room `{id}`!");
+      (
+        StatusCode::BAD_REQUEST,
+        Json(Resp { code: 4, msg: format!("User `{banned}` is not banned from the room `{id}`!") }),
+      )
+    },
+    Ok(true) => {
+      info!("User `{user}` lifted the ban of user `{banned}` from the room `{id}`");
+      (
+        StatusCode::OK,
+        Json(Resp { code: 0, msg: String::new() }),
+      )
+    },
+  }
+}
+
+/// Tells a field set to `null` apart from one that is left out.
+fn double_option<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+  T: Deserialize<'de>,
+  D: Deserializer<'de>,
+{
+  Option::<T>::deserialize(deserializer).map(Some)
+}
+
+/// The settings to change, leaving out the ones to keep.
+#[derive(Deserialize)]
+pub struct EditRoomPayload {
+  token: String,
+  name: Option<String>,
+  description: Option<String>,
+  /// An uploaded image, or `null` to remove the avatar.
+  #[serde(default, deserialize_with = "double_option")]
+  avatar: Option<Option<Uuid>>,
+  topic: Option<String>,
+}
+
+pub async fn edit_room(
+  State(state): State<Arc<AppState>>,
+  Path(id): Path<i32>,
+  Json(payload): Json<EditRoomPayload>,
+) -> (StatusCode, Json<ErrOr<room::Model>>) {
+  info!("PATCH /rooms/{id}");
+
+  let user = match auth(&state.db, &payload.token).await {
+    Ok(user) => user,
+    Err(err) => {
+      error!("{err}");
+      return (
+        StatusCode::UNAUTHORIZED,
+        Json(ErrOr::Err(Resp { code: 1, msg: err.to_string() })),
+      );
+    },
+  };
+
+  match utils::user_can(&state.db, user.id, id, Permission::EditRoom).await {
+    Err(err) => {
+      error!("{err}");
+      return (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(ErrOr::Err(Resp { code: 2, msg: "Error accessing database!".to_string() })),
+      );
+    },
+    Ok(false) => {
+      info!("User `{}` cannot edit the room `{id}`!", user.id);
+      return (
+        StatusCode::FORBIDDEN,
+        Json(ErrOr::Err(Resp { code: 3, msg: format!("You cannot edit the room `{id}`!") })),
+      );
+    },
+    Ok(true) => (),
+  }
+
+  let name = payload.name.map(|name| name.trim().to_string());
+
+  if name.as_ref().is_some_and(String::is_empty) {
+    info!("Empty room name!");
+    return (
+      StatusCode::BAD_REQUEST,
+      Json(ErrOr::Err(Resp { code: 4, msg: "The name of a room cannot be empty!".to_string() })),
+    );
+  }
+
+  if let Some(Some(avatar)) = payload.avatar {
+    let attachment = match utils::get_attachment(&state.db, avatar).await {
+      Err(err) => {
+        error!("{err}");
+        return (
+          StatusCode::INTERNAL_SERVER_ERROR,
+          Json(ErrOr::Err(Resp { code: 2, msg: "Error accessing database!".to_string() })),
+        );
+      },
+      Ok(attachment) => attachment,
+    };
+
+    let usable = match &attachment {
+      Some(attachment) if attachment.width.is_some() => {
+        match utils::can_access_attachment(&state.db, user.id, attachment).await {
+          Err(err) => {
+            error!("{err}");
+            return (
+              StatusCode::INTERNAL_SERVER_ERROR,
+              Json(ErrOr::Err(Resp { code: 2, msg: "Error accessing database!".to_string() })),
+            );
+          },
+          Ok(usable) => usable,
+        }
+      },
+      _ => false,
+    };
+
+    if !usable {
+      info!("The attachment `{avatar}` cannot be the avatar!");
+      return (
+        StatusCode::BAD_REQUEST,
+        Json(ErrOr::Err(Resp { code: 5, msg: format!("The attachment `{avatar}` is not an image you can use!") })),
+      );
+    }
+  }
+
+  let old_name = match utils::get_room(&state.db, id).await {
+    Ok(Some(room)) => room.name,
+    // Archived since the permission has been checked.
+    Ok(None) => {
+      info!("The room `{id}` has been archived!");
+      return (
+        StatusCode::FORBIDDEN,
+        Json(ErrOr::Err(Resp { code: 3, msg: format!("You cannot edit the room `{id}`!") })),
+      );
+    },
+    Err(err) => {
+      error!("{err}");
+      return (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(ErrOr::Err(Resp { code: 2, msg: "Error accessing database!".to_string() })),
+      );
+    },
+  };
+
+  let room = room::ActiveModel {
+    id: ActiveValue::Unchanged(id),
+    name: name.clone().map_or(ActiveValue::NotSet, ActiveValue::Set),
+    description: payload.description.map_or(ActiveValue::NotSet, ActiveValue::Set),
+    avatar: payload.avatar.map_or(ActiveValue::NotSet, ActiveValue::Set),
+    topic: payload.topic.map_or(ActiveValue::NotSet, ActiveValue::Set),
+    ..Default::default()
+  };
+
+  match utils::update_room(&state.db, room).await {
+    Err(err) => {
+      error!("{err}");
+      (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(ErrOr::Err(Resp { code: 6, msg: "Failed to update the room!".to_string() })),
+      )
+    },
+    Ok(room) => {
+      info!("User `{}` edited the room `{id}`", user.id);
+
+      state.registry.lock().unwrap().send_to_room(id, ChannelEvent::new_room(room.clone()));
+
+      if let Some(name) = name.filter(|name| *name != old_name) {
+        post_system_msg(&state, user.id, id, SystemMsg::Renamed { user: user.id, name }).await;
+      }
+
+      (StatusCode::OK, Json(ErrOr::Res(room)))
+    },
+  }
+}
+
+pub async fn archive_room(
+  State(state): State<Arc<AppState>>,
+  Path(id): Path<i32>,
+  TypedHeader(token): TypedHeader<Authorization<Bearer>>,
+) -> (StatusCode, Json<Resp>) {
+  info!("DELETE /rooms/{id}");
+
+  let user = match auth(&state.db, token.token()).await {
+    Ok(user) => user,
+    Err(err) => {
+      error!("{err}");
+      return (
+        StatusCode::UNAUTHORIZED,
+        Json(Resp { code: 1, msg: err.to_string() }),
+      );
+    },
+  };
+
+  match utils::user_can(&state.db, user.id, id, Permission::ArchiveRoom).await {
+    Err(err) => {
+      error!("{err}");
+      return (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(Resp { code: 2, msg: "Error accessing database!".to_string() }),
+      );
+    },
+    Ok(false) => {
+      info!("User `{}` cannot delete the room `{id}`!", user.id);
+      return (
+        StatusCode::FORBIDDEN,
+        Json(Resp { code: 3, msg: format!("You cannot delete the room `{id}`!") }),
+      );
+    },
+    Ok(true) => (),
+  }
+
+  match utils::archive_room(&state.db, id).await {
+    Err(err) => {
+      error!("{err}");
+      (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(Resp { code: 4, msg: format!("Failed to delete the room `{id}`!") }),
+      )
+    },
+    Ok(_) => {
+      info!("User `{}` archived the room `{id}`", user.id);
+
+      let mut registry = state.registry.lock().unwrap();
+
+      registry.send_to_room(id, ChannelEvent::new_archived(id));
+      registry.drop_room(id);
+
+      (
+        StatusCode::OK,
+        Json(Resp { code: 0, msg: String::new() }),
+      )
+    },
+  }
+}
@@ -0,0 +1,120 @@
+use std::sync::Arc;
+
+use axum::{
+  extract::{State, Query},
+  http::StatusCode,
+  Json,
+  TypedHeader,
+  headers::{Authorization, authorization::Bearer},
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{AppState, utils::{auth, self}, msg::SearchHit};
+
+use super::{ErrOr, Resp};
+
+const DEFAULT_PAGE_SIZE: u64 = 20;
+const MAX_PAGE_SIZE: u64 = 100;
+
+#[derive(Deserialize)]
+pub struct SearchQuery {
+  q: String,
+  /// Only searches this room instead of all the rooms of the user.
+  room: Option<i32>,
+  /// The last hit of the previous page.
+  before: Option<Uuid>,
+  limit: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct SearchResp {
+  hits: Vec<SearchHit>,
+  more: bool,
+}
+
+pub async fn search_msgs(
+  State(state): State<Arc<AppState>>,
+  Query(query): Query<SearchQuery>,
+  TypedHeader(token): TypedHeader<Authorization<Bearer>>,
+) -> (StatusCode, Json<ErrOr<SearchResp>>) {
+  info!("GET /search");
+
+  let user = match auth(&state.db, token.token()).await {
+    Ok(user) => user,
+    Err(err) => {
+      error!("{err}");
+      return (
+        StatusCode::UNAUTHORIZED,
+        Json(ErrOr::Err(Resp { code: 1, msg: err.to_string() })),
+      );
+    },
+  };
+
+  if query.q.trim().is_empty() {
+    info!("Empty search query!");
+    return (
+      StatusCode::BAD_REQUEST,
+      Json(ErrOr::Err(Resp { code: 2, msg: "The search query is empty!".to_string() })),
+    );
+  }
+
+  let rooms = match utils::get_user_rooms(&state.db, user.id).await {
+    Ok(rooms) => rooms,
+    Err(err) => {
+      error!("{err}");
+      return (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(ErrOr::Err(Resp { code: 3, msg: "Error accessing database!".to_string() })),
+      );
+    },
+  };
+
+  let rooms = match query.room {
+    None => rooms,
+    Some(room) if rooms.contains(&room) => vec![room],
+    Some(room) => {
+      info!("User `{}` is not in the room `{room}`!", user.id);
+      return (
+        StatusCode::FORBIDDEN,
+        Json(ErrOr::Err(Resp { code: 4, msg: format!("You are not in the room `{room}`!") })),
+      );
+    },
+  };
+
+  let before = match query.before {
+    None => None,
+    Some(uuid) => match utils::get_msg(&state.db, uuid).await {
+      Err(err) => {
+        error!("{err}");
+        return (
+          StatusCode::INTERNAL_SERVER_ERROR,
+          Json(ErrOr::Err(Resp { code: 3, msg: "Error accessing database!".to_string() })),
+        );
+      },
+      Ok(Some(msg)) if rooms.contains(&msg.room) => Some(msg),
+      Ok(_) => {
+        info!("The message `{uuid}` is not in the searched rooms!");
+        return (
+          StatusCode::BAD_REQUEST,
+          Json(ErrOr::Err(Resp { code: 5, msg: format!("The message `{uuid}` is not in the searched rooms!") })),
+        );
+      },
+    },
+  };
+
+  let limit = query.limit
+    .unwrap_or(DEFAULT_PAGE_SIZE)
+    .clamp(1, MAX_PAGE_SIZE);
+
+  match utils::search_msgs(&state.db, rooms, &query.q, before.as_ref(), limit).await {
+    Err(err) => {
+      error!("{err}");
+      (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(ErrOr::Err(Resp { code: 6, msg: "Failed to search the messages!".to_string() })),
+      )
+    },
+    Ok((hits, more)) => (StatusCode::OK, Json(ErrOr::Res(SearchResp { hits, more }))),
+  }
+}
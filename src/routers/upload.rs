@@ -0,0 +1,316 @@
+use std::{path::PathBuf, sync::Arc};
+
+use chrono::Local;
+use axum::{
+  extract::{State, Path, Multipart},
+  http::{StatusCode, header},
+  response::{IntoResponse, Response},
+  Json,
+  TypedHeader,
+  headers::{Authorization, authorization::Bearer},
+};
+use sea_orm::ActiveValue;
+use uuid::Uuid;
+
+use crate::{AppState, entities::attachment, utils::{auth, self}, media};
+
+use super::{ErrOr, Resp};
+
+/// The longest file name kept for an upload, in characters.
+const MAX_NAME_LEN: usize = 255;
+
+fn blob_path(state: &AppState, hash: &str) -> PathBuf {
+  state.config.upload_dir.join(hash)
+}
+
+/// Stores `data` under its hash unless the same content has been stored before.
+async fn store_blob(state: &AppState, hash: &str, data: &[u8]) -> std::io::Result<()> {
+  let path = blob_path(state, hash);
+
+  if tokio::fs::metadata(&path).await.is_ok() {
+    return Ok(());
+  }
+
+  // Written aside first so that a half written blob is never served.
+  let tmp = state.config.upload_dir.join(format!("{hash}.{}.tmp", Uuid::new_v4()));
+
+  tokio::fs::write(&tmp, data).await?;
+  tokio::fs::rename(&tmp, &path).await
+}
+
+/// Keeps the last component of a file name given by the client.
+fn clean_name(name: &str) -> String {
+  let name = name.rsplit(['/', '\\']).next().unwrap_or_default().trim();
+
+  match name {
+    "" => "file".to_string(),
+    name => name.chars().take(MAX_NAME_LEN).collect(),
+  }
+}
+
+pub async fn upload_file(
+  State(state): State<Arc<AppState>>,
+  TypedHeader(token): TypedHeader<Authorization<Bearer>>,
+  mut multipart: Multipart,
+) -> (StatusCode, Json<ErrOr<attachment::Model>>) {
+  info!("POST /uploads");
+
+  let user = match auth(&state.db, token.token()).await {
+    Ok(user) => user,
+    Err(err) => {
+      error!("{err}");
+      return (
+        StatusCode::UNAUTHORIZED,
+        Json(ErrOr::Err(Resp { code: 1, msg: err.to_string() })),
+      );
+    },
+  };
+
+  let field = loop {
+    match multipart.next_field().await {
+      Ok(Some(field)) if field.name() == Some("file") => break field,
+      Ok(Some(_)) => continue,
+      Ok(None) => {
+        info!("No `file` field in the upload!");
+        return (
+          StatusCode::BAD_REQUEST,
+          Json(ErrOr::Err(Resp { code: 2, msg: "The file must be sent in the `file` field!".to_string() })),
+        );
+      },
+      Err(err) => {
+        info!("{err}");
+        return (
+          StatusCode::BAD_REQUEST,
+          Json(ErrOr::Err(Resp { code: 3, msg: err.to_string() })),
+        );
+      },
+    }
+  };
+
+  let name = clean_name(field.file_name().unwrap_or_default());
+  let mime = field.content_type()
+    .unwrap_or("application/octet-stream")
+    .to_string();
+
+  let data = match field.bytes().await {
+    Ok(data) => data.to_vec(),
+    Err(err) => {
+      info!("{err}");
+      return (
+        StatusCode::BAD_REQUEST,
+        Json(ErrOr::Err(Resp { code: 3, msg: err.to_string() })),
+      );
+    },
+  };
+
+  // Decoding may take a while, so it is kept off the async workers.
+  let processed = tokio::task::spawn_blocking(move || {
+    let mut data = data;
+    media::strip_location(&mut data);
+    let image = media::process_image(&data);
+    (data, image)
+  }).await;
+
+  let (data, image) = match processed {
+    Ok(processed) => processed,
+    Err(err) => {
+      error!("{err}");
+      return (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(ErrOr::Err(Resp { code: 4, msg: "Failed to store the file!".to_string() })),
+      );
+    },
+  };
+
+  let hash = blake3::hash(&data).to_hex().to_string();
+
+  if let Err(err) = store_blob(&state, &hash, &data).await {
+    error!("{err}");
+    return (
+      StatusCode::INTERNAL_SERVER_ERROR,
+      Json(ErrOr::Err(Resp { code: 4, msg: "Failed to store the file!".to_string() })),
+    );
+  }
+
+  let mut attachment = attachment::ActiveModel {
+    id: ActiveValue::Set(Uuid::new_v4()),
+    uploader: ActiveValue::Set(user.id),
+    hash: ActiveValue::Set(hash),
+    name: ActiveValue::Set(name),
+    mime: ActiveValue::Set(mime),
+    size: ActiveValue::Set(data.len() as i64),
+    uploaded: ActiveValue::Set(Local::now()),
+    width: ActiveValue::Set(None),
+    height: ActiveValue::Set(None),
+    thumbnail: ActiveValue::Set(None),
+  };
+
+  if let Some(image) = image {
+    let thumbnail = match image.thumbnail {
+      None => None,
+      Some(thumbnail) => {
+        let hash = blake3::hash(&thumbnail).to_hex().to_string();
+
+        if let Err(err) = store_blob(&state, &hash, &thumbnail).await {
+          error!("{err}");
+          return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrOr::Err(Resp { code: 4, msg: "Failed to store the file!".to_string() })),
+          );
+        }
+
+        Some(hash)
+      },
+    };
+
+    // What the content says beats what the client claims.
+    attachment.mime = ActiveValue::Set(image.mime.to_string());
+    attachment.width = ActiveValue::Set(Some(image.width as i32));
+    attachment.height = ActiveValue::Set(Some(image.height as i32));
+    attachment.thumbnail = ActiveValue::Set(thumbnail);
+  }
+
+  match utils::add_attachment(&state.db, attachment).await {
+    Err(err) => {
+      error!("{err}");
+      (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(ErrOr::Err(Resp { code: 5, msg: "Failed to insert the attachment into the database!".to_string() })),
+      )
+    },
+    Ok(attachment) => {
+      info!("User `{}` uploaded the attachment `{}`", user.id, attachment.id);
+      (StatusCode::CREATED, Json(ErrOr::Res(attachment)))
+    },
+  }
+}
+
+/// Gets the attachment `id` if the user of `token` may download it.
+async fn get_accessible_attachment(
+  state: &AppState,
+  token: &str,
+  id: Uuid,
+) -> Result<attachment::Model, Response> {
+  let user = match auth(&state.db, token).await {
+    Ok(user) => user,
+    Err(err) => {
+      error!("{err}");
+      return Err((
+        StatusCode::UNAUTHORIZED,
+        Json(Resp { code: 1, msg: err.to_string() }),
+      ).into_response());
+    },
+  };
+
+  let attachment = match utils::get_attachment(&state.db, id).await {
+    Err(err) => {
+      error!("{err}");
+      return Err((
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(Resp { code: 2, msg: "Error accessing database!".to_string() }),
+      ).into_response());
+    },
+    Ok(None) => {
+      info!("The attachment `{id}` does not exist!");
+      return Err((
+        StatusCode::NOT_FOUND,
+        Json(Resp { code: 3, msg: format!("The attachment `{id}` does not exist!") }),
+      ).into_response());
+    },
+    Ok(Some(attachment)) => attachment,
+  };
+
+  match utils::can_access_attachment(&state.db, user.id, &attachment).await {
+    Err(err) => {
+      error!("{err}");
+      Err((
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(Resp { code: 2, msg: "Error accessing database!".to_string() }),
+      ).into_response())
+    },
+    Ok(false) => {
+      info!("User `{}` cannot access the attachment `{id}`!", user.id);
+      Err((
+        StatusCode::FORBIDDEN,
+        Json(Resp { code: 4, msg: format!("You cannot access the attachment `{id}`!") }),
+      ).into_response())
+    },
+    Ok(true) => Ok(attachment),
+  }
+}
+
+/// Serves the blob `hash` as a file called `name`, telling its type from the
+/// content if `mime` is not given.
+async fn serve_blob(state: &AppState, hash: &str, name: &str, mime: Option<&str>) -> Response {
+  let data = match tokio::fs::read(blob_path(state, hash)).await {
+    Ok(data) => data,
+    Err(err) => {
+      error!("Failed to read the blob `{hash}`: {err}");
+      return (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(Resp { code: 5, msg: "Failed to read the file!".to_string() }),
+      ).into_response();
+    },
+  };
+
+  let mime = mime
+    .or_else(|| media::image_mime(&data))
+    .unwrap_or("application/octet-stream");
+
+  // Only raster images are shown inline, anything else could run scripts in the page.
+  let inline = mime.starts_with("image/") && !mime.contains("svg");
+  let disposition = if inline { "inline" } else { "attachment" };
+  let name: String = name.chars()
+    .map(|c| if (c.is_ascii_graphic() || c == ' ') && c != '"' && c != '\\' { c } else { '_' })
+    .collect();
+
+  (
+    [
+      (header::CONTENT_TYPE, mime.to_string()),
+      (header::CONTENT_DISPOSITION, format!("{disposition}; filename=\"{name}\"")),
+      (header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+    ],
+    data,
+  ).into_response()
+}
+
+pub async fn download_file(
+  State(state): State<Arc<AppState>>,
+  Path(id): Path<Uuid>,
+  TypedHeader(token): TypedHeader<Authorization<Bearer>>,
+) -> Response {
+  info!("GET /uploads/{id}");
+
+  match get_accessible_attachment(&state, token.token(), id).await {
+    Err(resp) => resp,
+    Ok(attachment) => serve_blob(&state, &attachment.hash, &attachment.name, Some(&attachment.mime)).await,
+  }
+}
+
+/// Serves the thumbnail of an image, or the image itself if it is small.
+pub async fn download_thumbnail(
+  State(state): State<Arc<AppState>>,
+  Path(id): Path<Uuid>,
+  TypedHeader(token): TypedHeader<Authorization<Bearer>>,
+) -> Response {
+  info!("GET /uploads/{id}/thumbnail");
+
+  let attachment = match get_accessible_attachment(&state, token.token(), id).await {
+    Err(resp) => return resp,
+    Ok(attachment) => attachment,
+  };
+
+  if attachment.width.is_none() {
+    info!("The attachment `{id}` is not an image!");
+    return (
+      StatusCode::BAD_REQUEST,
+      Json(Resp { code: 6, msg: format!("The attachment `{id}` is not an image!") }),
+    ).into_response();
+  }
+
+  let Some(thumbnail) = attachment.thumbnail else {
+    return serve_blob(&state, &attachment.hash, &attachment.name, Some(&attachment.mime)).await;
+  };
+
+  serve_blob(&state, &thumbnail, &attachment.name, None).await
+}
@@ -5,13 +5,13 @@ use chrono::{Local, DateTime};
 use rand::Rng;
 use serde::{Deserialize, Serialize};
 use sea_orm::{ActiveValue, EntityTrait, QueryFilter, ColumnTrait, DatabaseConnection};
-use axum::{extract::{Json, State, TypedHeader, Path}, http::StatusCode, headers::UserAgent};
+use axum::{extract::{Json, State, TypedHeader, Path, Query}, http::StatusCode, headers::UserAgent};
 
-use crate::{AppState, entities::{prelude::*, user, session}};
+use crate::{AppState, entities::{prelude::*, user, session}, utils::auth, presence::{Presence, Availability}};
 
 use super::{Resp, ErrOr};
 
-#[derive(Serialize)]
+#[derive(Clone, Serialize)]
 pub struct UserWithoutPasswd {
   id: i32,
   username: String,
@@ -19,6 +19,7 @@ pub struct UserWithoutPasswd {
   slogan: String,
   status: i32,
   registered: DateTime<Local>,
+  availability: Availability,
 }
 
 impl UserWithoutPasswd {
@@ -30,6 +31,7 @@ impl UserWithoutPasswd {
       slogan: user.slogan,
       status: user.status,
       registered: user.registered,
+      availability: Availability::from_i32(user.availability),
     }
   }
 }
@@ -247,3 +249,165 @@ pub async fn get_user(
 
   (StatusCode::OK, Json(ErrOr::Res(UserWithoutPasswd::new(user))))
 }
+
+#[derive(Serialize)]
+pub struct UserPresence {
+  user: i32,
+  presence: Presence,
+  availability: Availability,
+  /// How many connections the user has open.
+  devices: usize,
+}
+
+impl UserPresence {
+  fn new(state: &AppState, user: &user::Model) -> Self {
+    let (presence, devices) = state.registry.lock().unwrap()
+      .presence(user.id, state.config.idle_after);
+
+    Self {
+      user: user.id,
+      presence,
+      availability: Availability::from_i32(user.availability),
+      devices,
+    }
+  }
+}
+
+pub async fn get_presence(
+  State(state): State<Arc<AppState>>,
+  Path(id): Path<i32>,
+) -> (StatusCode, Json<ErrOr<UserPresence>>) {
+  info!("GET /users/{id}/presence");
+
+  let user = User::find_by_id(id)
+    .one(&state.db).await;
+
+  if user.is_err() {
+    error!("Error accessing database!");
+    return (
+      StatusCode::INTERNAL_SERVER_ERROR,
+      Json(ErrOr::Err(Resp { code: 1, msg: "Error accessing database!".to_string() })),
+    );
+  }
+
+  let user = user.unwrap();
+
+  if user.is_none() {
+    info!("The user does not exist!");
+    return (
+      StatusCode::BAD_REQUEST,
+      Json(ErrOr::Err(Resp { code: 2, msg: "The user does not exist!".to_string() })),
+    );
+  }
+
+  let user = user.unwrap();
+
+  (StatusCode::OK, Json(ErrOr::Res(UserPresence::new(&state, &user))))
+}
+
+/// How many users can be asked for at once.
+const MAX_PRESENCE_USERS: usize = 100;
+
+#[derive(Deserialize)]
+pub struct PresenceQuery {
+  /// Comma separated user ids.
+  users: String,
+}
+
+pub async fn get_presence_list(
+  State(state): State<Arc<AppState>>,
+  Query(query): Query<PresenceQuery>,
+) -> (StatusCode, Json<ErrOr<Vec<UserPresence>>>) {
+  info!("GET /presence");
+
+  let ids = query.users
+    .split(',')
+    .map(|id| id.trim().parse::<i32>())
+    .collect::<Result<Vec<_>, _>>();
+
+  let ids = match ids {
+    Ok(ids) if ids.len() <= MAX_PRESENCE_USERS => ids,
+    Ok(_) => {
+      info!("Too many users!");
+      return (
+        StatusCode::BAD_REQUEST,
+        Json(ErrOr::Err(Resp { code: 1, msg: format!("At most {MAX_PRESENCE_USERS} users can be asked for at once!") })),
+      );
+    },
+    Err(err) => {
+      info!("{err}");
+      return (
+        StatusCode::BAD_REQUEST,
+        Json(ErrOr::Err(Resp { code: 2, msg: "Invalid user ids!".to_string() })),
+      );
+    },
+  };
+
+  let users = User::find()
+    .filter(user::Column::Id.is_in(ids))
+    .all(&state.db).await;
+
+  match users {
+    Err(_) => {
+      error!("Error accessing database!");
+      (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(ErrOr::Err(Resp { code: 3, msg: "Error accessing database!".to_string() })),
+      )
+    },
+    Ok(users) => {
+      let presences = users.iter()
+        .map(|user| UserPresence::new(&state, user))
+        .collect();
+
+      (StatusCode::OK, Json(ErrOr::Res(presences)))
+    },
+  }
+}
+
+#[derive(Deserialize)]
+pub struct AvailabilityPayload {
+  token: String,
+  availability: Availability,
+}
+
+pub async fn set_availability(
+  State(state): State<Arc<AppState>>,
+  Json(payload): Json<AvailabilityPayload>,
+) -> (StatusCode, Json<Resp>) {
+  info!("POST /users/me/availability");
+
+  let user = match auth(&state.db, &payload.token).await {
+    Ok(user) => user,
+    Err(err) => {
+      error!("{err}");
+      return (
+        StatusCode::UNAUTHORIZED,
+        Json(Resp { code: 1, msg: err.to_string() }),
+      );
+    },
+  };
+
+  let id = user.id;
+
+  let mut user: user::ActiveModel = user.into();
+  user.availability = ActiveValue::Set(payload.availability.to_i32());
+
+  match User::update(user).exec(&state.db).await {
+    Err(_) => {
+      error!("Failed to update the availability of user `{id}`!");
+      (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(Resp { code: 2, msg: "Failed to update the availability!".to_string() }),
+      )
+    },
+    Ok(_) => {
+      info!("User `{id}` is now {:?}", payload.availability);
+      state.registry.lock().unwrap().set_availability(id, payload.availability);
+      (
+        StatusCode::OK,
+        Json(Resp { code: 0, msg: String::new() }),
+      )
+    },
+  }
+}
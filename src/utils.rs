@@ -1,8 +1,11 @@
+use std::collections::HashMap;
+
 use anyhow::{Result, bail};
 use chrono::Local;
-use sea_orm::{EntityTrait, DatabaseConnection, ActiveValue};
+use uuid::Uuid;
+use sea_orm::{EntityTrait, DatabaseConnection, ActiveValue, QueryFilter, QueryOrder, QuerySelect, ColumnTrait, Condition, TransactionTrait, PaginatorTrait, FromQueryResult, Select, ConnectionTrait, Statement, DbBackend, Value, sea_query::{Expr, Query, SimpleExpr, OnConflict}};
 
-use crate::entities::{prelude::*, user, member, room};
+use crate::{entities::{prelude::*, user, member, room, message, message_revision, reaction, attachment, attachment_post, mention, invite, ban, dm}, msg::{Msg, MsgContent, SystemMsg, Revision, ReactionCount, ReplyPreview, SearchHit, MentionHit}, role::{Role, Permission, Visibility}};
 
 pub async fn auth(
   db: &DatabaseConnection,
@@ -34,29 +37,1183 @@ pub async fn auth(
   }
 }
 
+/// Keeps the memberships of archived rooms out of a query on `member`.
+fn in_active_room() -> SimpleExpr {
+  Expr::col((Member, member::Column::Room)).in_subquery(
+    Query::select()
+      .column(room::Column::Id)
+      .from(Room)
+      .and_where(room::Column::Archived.is_null())
+      .to_owned()
+  )
+}
+
 pub async fn user_in_room(
   db: &DatabaseConnection,
   user: i32,
   room: i32,
 ) -> Result<bool> {
   let member = Member::find_by_id((user, room))
+    .filter(in_active_room())
     .one(db).await?;
 
   Ok(member.is_some())
 }
 
-pub async fn join_room(
+/// The membership of `user` in `room`, `None` if they are not in it.
+pub async fn get_member(
+  db: &DatabaseConnection,
+  user: i32,
+  room: i32,
+) -> Result<Option<member::Model>> {
+  Ok(
+    Member::find_by_id((user, room))
+      .filter(in_active_room())
+      .one(db).await?
+  )
+}
+
+/// The role of `user` in `room`, `None` if they are not in it.
+pub async fn member_role(
+  db: &DatabaseConnection,
+  user: i32,
+  room: i32,
+) -> Result<Option<Role>> {
+  let member = get_member(db, user, room).await?;
+
+  Ok(member.map(|member| Role::from_i32(member.role)))
+}
+
+/// Whether `user` is in `room` with a role allowing `permission`.
+pub async fn user_can(
+  db: &DatabaseConnection,
+  user: i32,
+  room: i32,
+  permission: Permission,
+) -> Result<bool> {
+  Ok(member_role(db, user, room).await?.is_some_and(|role| role.can(permission)))
+}
+
+/// Whether `user` may delete `msg`: their own messages while they are still in
+/// the room, or any as a moderator. System messages record what happened in the
+/// room, so only moderators may remove them, even the ones posted on behalf of `user`.
+pub async fn can_delete_msg(
   db: &DatabaseConnection,
+  user: i32,
+  msg: &Msg,
+) -> Result<bool> {
+  if msg.sender == user && !matches!(msg.data, Some(MsgContent::System(_))) {
+    return user_in_room(db, user, msg.room).await;
+  }
+
+  user_can(db, user, msg.room, Permission::DeleteMsgs).await
+}
+
+pub async fn get_user_rooms(
+  db: &DatabaseConnection,
+  user: i32,
+) -> Result<Vec<i32>> {
+  let members = Member::find()
+    .filter(member::Column::User.eq(user))
+    .filter(in_active_room())
+    .all(db).await?;
+
+  Ok(members.into_iter().map(|member| member.room).collect())
+}
+
+/// The room `id`, unless it does not exist or has been archived.
+pub async fn get_room(
+  db: &DatabaseConnection,
+  id: i32,
+) -> Result<Option<room::Model>> {
+  Ok(
+    Room::find_by_id(id)
+      .filter(room::Column::Archived.is_null())
+      .one(db).await?
+  )
+}
+
+pub async fn update_room(
+  db: &DatabaseConnection,
+  room: room::ActiveModel,
+) -> Result<room::Model> {
+  Ok(Room::update(room).exec(db).await?)
+}
+
+/// Archives `room`, keeping its memberships and history but hiding them from everyone.
+pub async fn archive_room(
+  db: &DatabaseConnection,
+  room: i32,
+) -> Result<()> {
+  let room = room::ActiveModel {
+    id: ActiveValue::Unchanged(room),
+    archived: ActiveValue::Set(Some(Local::now())),
+    ..Default::default()
+  };
+
+  Room::update(room).exec(db).await?;
+
+  Ok(())
+}
+
+/// The memberships of public rooms, and of the rooms of `user` if given.
+pub async fn get_visible_members(
+  db: &DatabaseConnection,
+  user: Option<i32>,
+) -> Result<Vec<member::Model>> {
+  let rooms = match user {
+    Some(user) => get_user_rooms(db, user).await?,
+    None => vec![],
+  };
+
+  let public_rooms = Query::select()
+    .column(room::Column::Id)
+    .from(Room)
+    .and_where(room::Column::Visibility.eq(Visibility::Public.to_i32()))
+    .and_where(room::Column::Archived.is_null())
+    .to_owned();
+
+  Ok(
+    Member::find()
+      .filter(
+        Condition::any()
+          .add(member::Column::Room.in_subquery(public_rooms))
+          .add(member::Column::Room.is_in(rooms)),
+      )
+      .all(db).await?
+  )
+}
+
+pub async fn join_room<C: ConnectionTrait>(
+  db: &C,
   user: &user::Model,
   room: &room::Model,
+  role: Role,
 ) -> Result<()> {
   let new_member = member::ActiveModel {
     user: ActiveValue::Set(user.id),
     room: ActiveValue::Set(room.id),
     joined: ActiveValue::Set(Local::now()),
+    last_read: ActiveValue::Set(None),
+    role: ActiveValue::Set(role.to_i32()),
   };
 
   Member::insert(new_member).exec(db).await?;
 
   Ok(())
 }
+
+/// Saves `msg` unless its sender has already sent a message with the same uuid,
+/// returning the stored message and whether it is newly saved, or `None` if
+/// the uuid has been used by someone else.
+pub async fn save_msg(
+  db: &DatabaseConnection,
+  msg: Msg,
+) -> Result<Option<(Msg, bool)>> {
+  if let Some(saved) = get_msg(db, msg.uuid).await? {
+    if saved.sender != msg.sender {
+      return Ok(None);
+    }
+
+    return Ok(Some((saved, false)));
+  }
+
+  let txn = db.begin().await?;
+
+  if let Err(err) = Message::insert(msg.to_active_model()?).exec(&txn).await {
+    txn.rollback().await?;
+
+    // A retry on another connection may have saved the same message just now.
+    return match get_msg(db, msg.uuid).await? {
+      Some(saved) if saved.sender == msg.sender => Ok(Some((saved, false))),
+      _ => Err(err.into()),
+    };
+  }
+
+  post_attachment(&txn, &msg).await?;
+
+  txn.commit().await?;
+
+  Ok(Some((msg, true)))
+}
+
+/// Posts a message of the server about something `sender` did in `room`.
+pub async fn post_system_msg(
+  db: &DatabaseConnection,
+  sender: i32,
+  room: i32,
+  data: SystemMsg,
+) -> Result<Msg> {
+  let msg = Msg::system(sender, room, data);
+
+  Message::insert(msg.to_active_model()?).exec(db).await?;
+
+  Ok(msg)
+}
+
+pub async fn get_msg(
+  db: &DatabaseConnection,
+  uuid: Uuid,
+) -> Result<Option<Msg>> {
+  let Some(msg) = Message::find_by_id(uuid).one(db).await? else {
+    return Ok(None);
+  };
+
+  let mut msgs = vec![Msg::from_model(msg)?];
+  load_details(db, &mut msgs).await?;
+
+  Ok(msgs.pop())
+}
+
+/// Fills in what is shown along with `msgs` but stored elsewhere.
+async fn load_details(
+  db: &DatabaseConnection,
+  msgs: &mut [Msg],
+) -> Result<()> {
+  load_reactions(db, msgs).await?;
+  load_replies(db, msgs).await
+}
+
+/// Fills in the reactions of `msgs`.
+async fn load_reactions(
+  db: &DatabaseConnection,
+  msgs: &mut [Msg],
+) -> Result<()> {
+  if msgs.is_empty() {
+    return Ok(());
+  }
+
+  let reactions = Reaction::find()
+    .filter(reaction::Column::Message.is_in(msgs.iter().map(|msg| msg.uuid)))
+    .order_by_asc(reaction::Column::Reacted)
+    .all(db).await?;
+
+  let mut counts: HashMap<Uuid, Vec<ReactionCount>> = HashMap::new();
+
+  for reaction in reactions {
+    let counts = counts.entry(reaction.message).or_default();
+
+    match counts.iter_mut().find(|count| count.emoji == reaction.emoji) {
+      Some(count) => {
+        count.count += 1;
+        count.users.push(reaction.user);
+      },
+      None => counts.push(ReactionCount {
+        emoji: reaction.emoji,
+        count: 1,
+        users: vec![reaction.user],
+      }),
+    }
+  }
+
+  for msg in msgs {
+    msg.reactions = counts.remove(&msg.uuid).unwrap_or_default();
+  }
+
+  Ok(())
+}
+
+#[derive(FromQueryResult)]
+struct ThreadReplies {
+  thread_root: Uuid,
+  count: i64,
+}
+
+/// Fills in the previews of the messages replied to by `msgs` and the sizes
+/// of the threads they start.
+async fn load_replies(
+  db: &DatabaseConnection,
+  msgs: &mut [Msg],
+) -> Result<()> {
+  if msgs.is_empty() {
+    return Ok(());
+  }
+
+  let replied: Vec<Uuid> = msgs.iter().filter_map(|msg| msg.reply_to).collect();
+
+  let mut previews: HashMap<Uuid, ReplyPreview> = HashMap::new();
+
+  if !replied.is_empty() {
+    for model in Message::find()
+      .filter(message::Column::Uuid.is_in(replied))
+      .all(db).await?
+    {
+      let msg = Msg::from_model(model)?;
+
+      previews.insert(msg.uuid, ReplyPreview {
+        uuid: msg.uuid,
+        sender: msg.sender,
+        preview: msg.data.map(|data| data.preview()),
+      });
+    }
+  }
+
+  let threads: HashMap<Uuid, u64> = Message::find()
+    .select_only()
+    .column(message::Column::ThreadRoot)
+    .column_as(Expr::col(message::Column::Uuid).count(), "count")
+    .filter(message::Column::ThreadRoot.is_in(msgs.iter().map(|msg| msg.uuid)))
+    .filter(message::Column::Deleted.is_null())
+    .group_by(message::Column::ThreadRoot)
+    .into_model::<ThreadReplies>()
+    .all(db).await?
+    .into_iter()
+    .map(|thread| (thread.thread_root, thread.count as u64))
+    .collect();
+
+  for msg in msgs {
+    msg.reply_preview = msg.reply_to.and_then(|uuid| previews.get(&uuid).cloned());
+    msg.thread_replies = threads.get(&msg.uuid).copied().unwrap_or(0);
+  }
+
+  Ok(())
+}
+
+// Messages sent at the same instant are ordered by uuid so that cursors stay stable.
+
+fn sent_before(msg: &Msg) -> Condition {
+  Condition::any()
+    .add(message::Column::Sent.lt(msg.sent))
+    .add(
+      Condition::all()
+        .add(message::Column::Sent.eq(msg.sent))
+        .add(message::Column::Uuid.lt(msg.uuid)),
+    )
+}
+
+fn sent_after(msg: &Msg) -> Condition {
+  Condition::any()
+    .add(message::Column::Sent.gt(msg.sent))
+    .add(
+      Condition::all()
+        .add(message::Column::Sent.eq(msg.sent))
+        .add(message::Column::Uuid.gt(msg.uuid)),
+    )
+}
+
+/// Where a page of room history starts, relative to an existing message.
+pub enum Cursor<'a> {
+  Latest,
+  Before(&'a Msg),
+  After(&'a Msg),
+}
+
+/// Returns up to `limit` messages of `room` next to `cursor` in ascending
+/// order of `sent`, and whether there are more beyond the page.
+pub async fn get_room_msgs(
+  db: &DatabaseConnection,
+  room: i32,
+  cursor: Cursor<'_>,
+  limit: u64,
+) -> Result<(Vec<Msg>, bool)> {
+  let query = Message::find()
+    .filter(message::Column::Room.eq(room));
+
+  get_page(db, query, cursor, limit).await
+}
+
+/// Returns up to `limit` messages posted in the thread started by `root`,
+/// like [`get_room_msgs`].
+pub async fn get_thread_msgs(
+  db: &DatabaseConnection,
+  root: Uuid,
+  cursor: Cursor<'_>,
+  limit: u64,
+) -> Result<(Vec<Msg>, bool)> {
+  let query = Message::find()
+    .filter(message::Column::ThreadRoot.eq(root));
+
+  get_page(db, query, cursor, limit).await
+}
+
+async fn get_page(
+  db: &DatabaseConnection,
+  query: Select<Message>,
+  cursor: Cursor<'_>,
+  limit: u64,
+) -> Result<(Vec<Msg>, bool)> {
+  let ascending = matches!(cursor, Cursor::After(_));
+
+  let query = match cursor {
+    Cursor::Latest => query
+      .order_by_desc(message::Column::Sent)
+      .order_by_desc(message::Column::Uuid),
+    Cursor::Before(msg) => query
+      .filter(sent_before(msg))
+      .order_by_desc(message::Column::Sent)
+      .order_by_desc(message::Column::Uuid),
+    Cursor::After(msg) => query
+      .filter(sent_after(msg))
+      .order_by_asc(message::Column::Sent)
+      .order_by_asc(message::Column::Uuid),
+  };
+
+  let mut msgs = query
+    .limit(limit + 1)
+    .all(db).await?
+    .into_iter()
+    .map(Msg::from_model)
+    .collect::<Result<Vec<_>>>()?;
+
+  let more = msgs.len() as u64 > limit;
+  msgs.truncate(limit as usize);
+
+  load_details(db, &mut msgs).await?;
+
+  if !ascending {
+    msgs.reverse();
+  }
+
+  Ok((msgs, more))
+}
+
+/// Returns up to `limit` messages sent in any of `rooms` after `since` in
+/// ascending order of `sent`, and whether there are more.
+pub async fn get_msgs_since(
+  db: &DatabaseConnection,
+  rooms: Vec<i32>,
+  since: &Msg,
+  limit: u64,
+) -> Result<(Vec<Msg>, bool)> {
+  let mut msgs = Message::find()
+    .filter(message::Column::Room.is_in(rooms))
+    .filter(sent_after(since))
+    .order_by_asc(message::Column::Sent)
+    .order_by_asc(message::Column::Uuid)
+    .limit(limit + 1)
+    .all(db).await?
+    .into_iter()
+    .map(Msg::from_model)
+    .collect::<Result<Vec<_>>>()?;
+
+  let more = msgs.len() as u64 > limit;
+  msgs.truncate(limit as usize);
+
+  load_details(db, &mut msgs).await?;
+
+  Ok((msgs, more))
+}
+
+/// Replaces the content of `msg`, keeping the previous content as a revision.
+pub async fn edit_msg(
+  db: &DatabaseConnection,
+  mut msg: Msg,
+  data: MsgContent,
+) -> Result<Msg> {
+  let now = Local::now();
+
+  let revision = message_revision::ActiveModel {
+    message: ActiveValue::Set(msg.uuid),
+    data: ActiveValue::Set(serde_json::to_string(&msg.data)?),
+    written: ActiveValue::Set(msg.edited.unwrap_or(msg.sent)),
+    replaced: ActiveValue::Set(now),
+    ..Default::default()
+  };
+
+  msg.data = Some(data);
+  msg.modified = true;
+  msg.edited = Some(now);
+
+  let txn = db.begin().await?;
+
+  MessageRevision::insert(revision).exec(&txn).await?;
+  Message::update(msg.to_active_model()?).exec(&txn).await?;
+  // The room keeps access only to the file the message now points at.
+  AttachmentPost::delete_many()
+    .filter(attachment_post::Column::Message.eq(msg.uuid))
+    .exec(&txn).await?;
+  post_attachment(&txn, &msg).await?;
+
+  txn.commit().await?;
+
+  Ok(msg)
+}
+
+pub async fn get_revisions(
+  db: &DatabaseConnection,
+  uuid: Uuid,
+) -> Result<Vec<Revision>> {
+  MessageRevision::find()
+    .filter(message_revision::Column::Message.eq(uuid))
+    .order_by_asc(message_revision::Column::Id)
+    .all(db).await?
+    .into_iter()
+    .map(Revision::from_model)
+    .collect()
+}
+
+/// Tombstones `msg`, dropping its content together with all its revisions,
+/// reactions, attachments and mentions.
+pub async fn delete_msg(
+  db: &DatabaseConnection,
+  mut msg: Msg,
+) -> Result<Msg> {
+  msg.data = None;
+  msg.deleted = Some(Local::now());
+  msg.reactions.clear();
+
+  let txn = db.begin().await?;
+
+  MessageRevision::delete_many()
+    .filter(message_revision::Column::Message.eq(msg.uuid))
+    .exec(&txn).await?;
+  Reaction::delete_many()
+    .filter(reaction::Column::Message.eq(msg.uuid))
+    .exec(&txn).await?;
+  AttachmentPost::delete_many()
+    .filter(attachment_post::Column::Message.eq(msg.uuid))
+    .exec(&txn).await?;
+  Mention::delete_many()
+    .filter(mention::Column::Message.eq(msg.uuid))
+    .exec(&txn).await?;
+  Message::update(msg.to_active_model()?).exec(&txn).await?;
+
+  txn.commit().await?;
+
+  Ok(msg)
+}
+
+/// Moves the read marker of `user` in the room of `msg` forward to `msg`,
+/// returning whether it moved.
+pub async fn mark_read(
+  db: &DatabaseConnection,
+  user: i32,
+  msg: &Msg,
+) -> Result<bool> {
+  let Some(member) = Member::find_by_id((user, msg.room)).one(db).await? else {
+    bail!("User `{user}` is not in the room `{}`!", msg.room);
+  };
+
+  if let Some(last_read) = member.last_read {
+    if let Some(last_read) = get_msg(db, last_read).await? {
+      if (last_read.sent, last_read.uuid) >= (msg.sent, msg.uuid) {
+        return Ok(false);
+      }
+    }
+  }
+
+  let mut member: member::ActiveModel = member.into();
+  member.last_read = ActiveValue::Set(Some(msg.uuid));
+
+  Member::update(member).exec(db).await?;
+
+  Ok(true)
+}
+
+/// Counts the messages of others in `room` after the `last_read` one.
+pub async fn count_unread(
+  db: &DatabaseConnection,
+  user: i32,
+  room: i32,
+  last_read: Option<Uuid>,
+) -> Result<u64> {
+  let mut query = Message::find()
+    .filter(message::Column::Room.eq(room))
+    .filter(message::Column::Sender.ne(user))
+    .filter(message::Column::Deleted.is_null());
+
+  if let Some(last_read) = last_read {
+    if let Some(last_read) = get_msg(db, last_read).await? {
+      query = query.filter(sent_after(&last_read));
+    }
+  }
+
+  Ok(query.count(db).await? as u64)
+}
+
+/// Adds the reaction of `user` with `emoji` to `msg`, returning whether it is new.
+pub async fn add_reaction(
+  db: &DatabaseConnection,
+  user: i32,
+  msg: &Msg,
+  emoji: &str,
+) -> Result<bool> {
+  let key = (msg.uuid, user, emoji.to_string());
+
+  if Reaction::find_by_id(key.clone()).one(db).await?.is_some() {
+    return Ok(false);
+  }
+
+  let reaction = reaction::ActiveModel {
+    message: ActiveValue::Set(msg.uuid),
+    user: ActiveValue::Set(user),
+    emoji: ActiveValue::Set(emoji.to_string()),
+    reacted: ActiveValue::Set(Local::now()),
+  };
+
+  if let Err(err) = Reaction::insert(reaction).exec(db).await {
+    // Another connection of the user may have added it just now.
+    return match Reaction::find_by_id(key).one(db).await? {
+      Some(_) => Ok(false),
+      None => Err(err.into()),
+    };
+  }
+
+  Ok(true)
+}
+
+/// Removes the reaction of `user` with `emoji` from `msg`, returning whether there was one.
+pub async fn remove_reaction(
+  db: &DatabaseConnection,
+  user: i32,
+  msg: &Msg,
+  emoji: &str,
+) -> Result<bool> {
+  let result = Reaction::delete_by_id((msg.uuid, user, emoji.to_string()))
+    .exec(db).await?;
+
+  Ok(result.rows_affected > 0)
+}
+
+pub async fn add_attachment(
+  db: &DatabaseConnection,
+  attachment: attachment::ActiveModel,
+) -> Result<attachment::Model> {
+  let id = Attachment::insert(attachment).exec(db).await?.last_insert_id;
+
+  let Some(attachment) = Attachment::find_by_id(id).one(db).await? else {
+    bail!("Attachment `{id}` not found after inserting it!");
+  };
+
+  Ok(attachment)
+}
+
+pub async fn get_attachment(
+  db: &DatabaseConnection,
+  id: Uuid,
+) -> Result<Option<attachment::Model>> {
+  Ok(Attachment::find_by_id(id).one(db).await?)
+}
+
+/// Whether `user` uploaded `attachment`, is in a room where it has been posted,
+/// or can see a room using it as the avatar.
+pub async fn can_access_attachment(
+  db: &DatabaseConnection,
+  user: i32,
+  attachment: &attachment::Model,
+) -> Result<bool> {
+  if attachment.uploader == user {
+    return Ok(true);
+  }
+
+  let rooms = get_user_rooms(db, user).await?;
+
+  let posts = AttachmentPost::find()
+    .filter(attachment_post::Column::Attachment.eq(attachment.id))
+    .filter(attachment_post::Column::Room.is_in(rooms.clone()))
+    .count(db).await?;
+
+  if posts > 0 {
+    return Ok(true);
+  }
+
+  let avatars = Room::find()
+    .filter(room::Column::Avatar.eq(attachment.id))
+    .filter(room::Column::Archived.is_null())
+    .filter(
+      Condition::any()
+        .add(room::Column::Visibility.eq(Visibility::Public.to_i32()))
+        .add(room::Column::Id.is_in(rooms)),
+    )
+    .count(db).await?;
+
+  Ok(avatars > 0)
+}
+
+/// Records that the attachment of `msg`, if any, has been posted in its room.
+async fn post_attachment<C: ConnectionTrait>(
+  db: &C,
+  msg: &Msg,
+) -> Result<()> {
+  let Some(attachment) = msg.data.as_ref().and_then(MsgContent::attachment) else {
+    return Ok(());
+  };
+
+  if AttachmentPost::find_by_id((attachment, msg.uuid)).one(db).await?.is_some() {
+    return Ok(());
+  }
+
+  let post = attachment_post::ActiveModel {
+    attachment: ActiveValue::Set(attachment),
+    message: ActiveValue::Set(msg.uuid),
+    room: ActiveValue::Set(msg.room),
+  };
+
+  AttachmentPost::insert(post).exec(db).await?;
+
+  Ok(())
+}
+
+#[derive(FromQueryResult)]
+struct SearchRow {
+  uuid: Uuid,
+  snippet: String,
+}
+
+/// Turns what the user typed into an FTS5 query matching messages with all
+/// the words, so that no word is taken for an operator.
+fn fts_query(query: &str) -> Option<String> {
+  let terms: Vec<String> = query.split_whitespace()
+    .map(|term| format!("\"{}\"", term.replace('"', "\"\"")))
+    .collect();
+
+  (!terms.is_empty()).then(|| terms.join(" "))
+}
+
+/// What FTS5 wraps matched terms in, replaced by `<mark>` once the text is escaped.
+const MATCH_START: char = '\u{2}';
+const MATCH_END: char = '\u{3}';
+
+/// Escapes a snippet of message text for HTML, keeping only the highlighting as markup.
+fn highlight_snippet(snippet: &str) -> String {
+  let mut html = String::with_capacity(snippet.len());
+
+  for c in snippet.chars() {
+    match c {
+      MATCH_START => html.push_str("<mark>"),
+      MATCH_END => html.push_str("</mark>"),
+      '&' => html.push_str("&amp;"),
+      '<' => html.push_str("&lt;"),
+      '>' => html.push_str("&gt;"),
+      '"' => html.push_str("&quot;"),
+      '\'' => html.push_str("&#39;"),
+      c => html.push(c),
+    }
+  }
+
+  html
+}
+
+/// Returns up to `limit` messages in `rooms` matching `query`, newest first
+/// and sent before `before` if given, and whether there are more.
+pub async fn search_msgs(
+  db: &DatabaseConnection,
+  rooms: Vec<i32>,
+  query: &str,
+  before: Option<&Msg>,
+  limit: u64,
+) -> Result<(Vec<SearchHit>, bool)> {
+  let Some(query) = fts_query(query) else {
+    return Ok((vec![], false));
+  };
+
+  if rooms.is_empty() {
+    return Ok((vec![], false));
+  }
+
+  let mut values: Vec<Value> = vec![query.into()];
+
+  let rooms_sql = vec!["?"; rooms.len()].join(", ");
+  values.extend(rooms.into_iter().map(Value::from));
+
+  let before_sql = match before {
+    None => "",
+    Some(msg) => {
+      values.extend([msg.sent.into(), msg.sent.into(), msg.uuid.into()]);
+      "AND (m.sent < ? OR (m.sent = ? AND m.uuid < ?))"
+    },
+  };
+
+  values.push(((limit + 1) as i64).into());
+
+  let sql = format!(
+    "SELECT f.uuid AS uuid, snippet(message_fts, 1, char(2), char(3), '…', 16) AS snippet \
+      FROM message_fts f JOIN message m ON m.uuid = f.uuid \
+      WHERE message_fts MATCH ? AND m.room IN ({rooms_sql}) AND m.deleted IS NULL {before_sql} \
+      ORDER BY m.sent DESC, m.uuid DESC \
+      LIMIT ?"
+  );
+
+  let mut rows = SearchRow::find_by_statement(Statement::from_sql_and_values(DbBackend::Sqlite, &sql, values))
+    .all(db).await?;
+
+  let more = rows.len() as u64 > limit;
+  rows.truncate(limit as usize);
+
+  let mut msgs: HashMap<Uuid, Msg> = HashMap::new();
+
+  if !rows.is_empty() {
+    let mut found = Message::find()
+      .filter(message::Column::Uuid.is_in(rows.iter().map(|row| row.uuid)))
+      .all(db).await?
+      .into_iter()
+      .map(Msg::from_model)
+      .collect::<Result<Vec<_>>>()?;
+
+    load_details(db, &mut found).await?;
+
+    msgs.extend(found.into_iter().map(|msg| (msg.uuid, msg)));
+  }
+
+  let hits = rows.into_iter()
+    .filter_map(|row| Some(SearchHit { msg: msgs.remove(&row.uuid)?, snippet: highlight_snippet(&row.snippet) }))
+    .collect();
+
+  Ok((hits, more))
+}
+
+/// Records the members of the room of `msg` it mentions and forgets the ones
+/// it no longer does after an edit, returning the ones not mentioned by it before.
+pub async fn save_mentions(
+  db: &DatabaseConnection,
+  msg: &Msg,
+) -> Result<Vec<i32>> {
+  let usernames = msg.data.as_ref()
+    .map(MsgContent::mentions)
+    .unwrap_or_default();
+
+  let users = if usernames.is_empty() {
+    vec![]
+  } else {
+    User::find()
+      .filter(user::Column::Username.is_in(usernames))
+      .filter(user::Column::Id.ne(msg.sender))
+      .all(db).await?
+  };
+
+  Mention::delete_many()
+    .filter(mention::Column::Message.eq(msg.uuid))
+    .filter(mention::Column::User.is_not_in(users.iter().map(|user| user.id)))
+    .exec(db).await?;
+
+  let mentioned: Vec<i32> = Mention::find()
+    .filter(mention::Column::Message.eq(msg.uuid))
+    .all(db).await?
+    .into_iter()
+    .map(|mention| mention.user)
+    .collect();
+
+  let mut new = vec![];
+
+  for user in users {
+    if mentioned.contains(&user.id) || !user_in_room(db, user.id, msg.room).await? {
+      continue;
+    }
+
+    let mention = mention::ActiveModel {
+      message: ActiveValue::Set(msg.uuid),
+      user: ActiveValue::Set(user.id),
+      room: ActiveValue::Set(msg.room),
+      mentioned: ActiveValue::Set(Local::now()),
+    };
+
+    Mention::insert(mention).exec(db).await?;
+
+    new.push(user.id);
+  }
+
+  Ok(new)
+}
+
+/// Returns up to `limit` messages in the rooms of `user` mentioning them,
+/// newest first and older than the `before` mention if given, and whether
+/// there are more.
+pub async fn get_mentions(
+  db: &DatabaseConnection,
+  user: i32,
+  before: Option<&mention::Model>,
+  limit: u64,
+) -> Result<(Vec<MentionHit>, bool)> {
+  let mut query = Mention::find()
+    .filter(mention::Column::User.eq(user))
+    .filter(mention::Column::Room.is_in(get_user_rooms(db, user).await?));
+
+  if let Some(before) = before {
+    query = query.filter(
+      Condition::any()
+        .add(mention::Column::Mentioned.lt(before.mentioned))
+        .add(
+          Condition::all()
+            .add(mention::Column::Mentioned.eq(before.mentioned))
+            .add(mention::Column::Message.lt(before.message)),
+        ),
+    );
+  }
+
+  let mut mentions = query
+    .order_by_desc(mention::Column::Mentioned)
+    .order_by_desc(mention::Column::Message)
+    .limit(limit + 1)
+    .all(db).await?;
+
+  let more = mentions.len() as u64 > limit;
+  mentions.truncate(limit as usize);
+
+  let mut msgs: HashMap<Uuid, Msg> = HashMap::new();
+
+  if !mentions.is_empty() {
+    let mut found = Message::find()
+      .filter(message::Column::Uuid.is_in(mentions.iter().map(|mention| mention.message)))
+      .all(db).await?
+      .into_iter()
+      .map(Msg::from_model)
+      .collect::<Result<Vec<_>>>()?;
+
+    load_details(db, &mut found).await?;
+
+    msgs.extend(found.into_iter().map(|msg| (msg.uuid, msg)));
+  }
+
+  let hits = mentions.into_iter()
+    .filter_map(|mention| Some(MentionHit { msg: msgs.remove(&mention.message)?, mentioned: mention.mentioned }))
+    .collect();
+
+  Ok((hits, more))
+}
+
+pub async fn get_mention(
+  db: &DatabaseConnection,
+  user: i32,
+  uuid: Uuid,
+) -> Result<Option<mention::Model>> {
+  Ok(Mention::find_by_id((uuid, user)).one(db).await?)
+}
+
+/// Gives `user` the `role` in `room`; handing over the ownership makes the
+/// previous `owner` an admin.
+pub async fn set_role(
+  db: &DatabaseConnection,
+  room: i32,
+  user: i32,
+  role: Role,
+  owner: Option<i32>,
+) -> Result<()> {
+  let txn = db.begin().await?;
+
+  let member = member::ActiveModel {
+    user: ActiveValue::Unchanged(user),
+    room: ActiveValue::Unchanged(room),
+    role: ActiveValue::Set(role.to_i32()),
+    ..Default::default()
+  };
+
+  Member::update(member).exec(&txn).await?;
+
+  if let Some(owner) = owner {
+    let member = member::ActiveModel {
+      user: ActiveValue::Unchanged(owner),
+      room: ActiveValue::Unchanged(room),
+      role: ActiveValue::Set(Role::Admin.to_i32()),
+      ..Default::default()
+    };
+
+    Member::update(member).exec(&txn).await?;
+  }
+
+  txn.commit().await?;
+
+  Ok(())
+}
+
+pub async fn add_invite(
+  db: &DatabaseConnection,
+  invite: invite::ActiveModel,
+) -> Result<invite::Model> {
+  let code = Invite::insert(invite).exec(db).await?.last_insert_id;
+
+  match Invite::find_by_id(code).one(db).await? {
+    Some(invite) => Ok(invite),
+    None => bail!("The new invite is missing!"),
+  }
+}
+
+pub async fn get_invite(
+  db: &DatabaseConnection,
+  code: &str,
+) -> Result<Option<invite::Model>> {
+  Ok(Invite::find_by_id(code.to_string()).one(db).await?)
+}
+
+/// Joins `user` to the room of `invite` as a member, returning `false` if the
+/// invite has been used up in the meantime.
+pub async fn accept_invite(
+  db: &DatabaseConnection,
+  user: &user::Model,
+  room: &room::Model,
+  invite: &invite::Model,
+) -> Result<bool> {
+  let txn = db.begin().await?;
+
+  // Counted in the same statement as checked, so that concurrent accepts cannot overuse it.
+  let result = Invite::update_many()
+    .col_expr(invite::Column::Uses, Expr::col(invite::Column::Uses).add(1))
+    .filter(invite::Column::Code.eq(invite.code.clone()))
+    .filter(
+      Condition::any()
+        .add(invite::Column::MaxUses.is_null())
+        .add(Expr::col(invite::Column::Uses).less_than(Expr::col(invite::Column::MaxUses))),
+    )
+    .exec(&txn).await?;
+
+  if result.rows_affected == 0 {
+    txn.rollback().await?;
+    return Ok(false);
+  }
+
+  join_room(&txn, user, room, Role::Member).await?;
+
+  txn.commit().await?;
+
+  Ok(true)
+}
+
+pub async fn is_banned(
+  db: &DatabaseConnection,
+  user: i32,
+  room: i32,
+) -> Result<bool> {
+  Ok(Ban::find_by_id((room, user)).one(db).await?.is_some())
+}
+
+/// Takes `user` out of `room`, banning them from it too if `banned_by` is given.
+pub async fn remove_member(
+  db: &DatabaseConnection,
+  room: i32,
+  user: i32,
+  banned_by: Option<i32>,
+) -> Result<()> {
+  let txn = db.begin().await?;
+
+  Member::delete_by_id((user, room)).exec(&txn).await?;
+
+  if let Some(by) = banned_by {
+    let ban = ban::ActiveModel {
+      room: ActiveValue::Set(room),
+      user: ActiveValue::Set(user),
+      by: ActiveValue::Set(by),
+      banned: ActiveValue::Set(Local::now()),
+    };
+
+    Ban::insert(ban).exec(&txn).await?;
+  }
+
+  txn.commit().await?;
+
+  Ok(())
+}
+
+pub async fn get_bans(
+  db: &DatabaseConnection,
+  room: i32,
+) -> Result<Vec<ban::Model>> {
+  Ok(
+    Ban::find()
+      .filter(ban::Column::Room.eq(room))
+      .order_by_asc(ban::Column::Banned)
+      .all(db).await?
+  )
+}
+
+/// Lifts the ban of `user` from `room`, returning whether they were banned.
+pub async fn unban(
+  db: &DatabaseConnection,
+  room: i32,
+  user: i32,
+) -> Result<bool> {
+  let result = Ban::delete_by_id((room, user)).exec(db).await?;
+
+  Ok(result.rows_affected > 0)
+}
+
+/// A pair of users in the order they are stored in `dm`.
+fn dm_pair(user: i32, peer: i32) -> (i32, i32) {
+  (user.min(peer), user.max(peer))
+}
+
+/// The room `user` and `peer` talk privately in, if they have started to.
+pub async fn get_dm_room(
+  db: &DatabaseConnection,
+  user: i32,
+  peer: i32,
+) -> Result<Option<room::Model>> {
+  let Some(dm) = Dm::find_by_id(dm_pair(user, peer)).one(db).await? else {
+    return Ok(None);
+  };
+
+  get_room(db, dm.room).await
+}
+
+/// Creates the room `user` and `peer` talk privately in, returning `None` if
+/// it has just been created by someone else.
+pub async fn new_dm_room(
+  db: &DatabaseConnection,
+  user: &user::Model,
+  peer: &user::Model,
+) -> Result<Option<room::Model>> {
+  let txn = db.begin().await?;
+
+  let room = room::ActiveModel {
+    name: ActiveValue::Set(String::new()),
+    description: ActiveValue::Set(String::new()),
+    created: ActiveValue::Set(Local::now()),
+    visibility: ActiveValue::Set(Visibility::Direct.to_i32()),
+    avatar: ActiveValue::Set(None),
+    topic: ActiveValue::Set(String::new()),
+    archived: ActiveValue::Set(None),
+    ..Default::default()
+  };
+
+  let room = Room::insert(room).exec(&txn).await?.last_insert_id;
+
+  let (user_a, user_b) = dm_pair(user.id, peer.id);
+
+  let dm = dm::ActiveModel {
+    user_a: ActiveValue::Set(user_a),
+    user_b: ActiveValue::Set(user_b),
+    room: ActiveValue::Set(room),
+  };
+
+  let inserted = Dm::insert(dm)
+    .on_conflict(
+      OnConflict::columns([dm::Column::UserA, dm::Column::UserB])
+        .do_nothing()
+        .to_owned()
+    )
+    .exec_without_returning(&txn).await?;
+
+  if inserted == 0 {
+    txn.rollback().await?;
+    return Ok(None);
+  }
+
+  let Some(room) = Room::find_by_id(room).one(&txn).await? else {
+    bail!("The new room `{room}` is missing!");
+  };
+
+  join_room(&txn, user, &room, Role::Member).await?;
+  join_room(&txn, peer, &room, Role::Member).await?;
+
+  txn.commit().await?;
+
+  Ok(Some(room))
+}
+
+/// The other user in the private room `room` of `user`.
+pub async fn get_dm_peer(
+  db: &DatabaseConnection,
+  room: i32,
+  user: i32,
+) -> Result<Option<user::Model>> {
+  let Some(dm) = Dm::find().filter(dm::Column::Room.eq(room)).one(db).await? else {
+    return Ok(None);
+  };
+
+  let peer = if dm.user_a == user { dm.user_b } else { dm.user_a };
+
+  Ok(User::find_by_id(peer).one(db).await?)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn escapes_snippets_around_matches() {
+    let snippet = format!("<img src=x onerror=\"alert('&')\"> {MATCH_START}hit{MATCH_END}");
+
+    assert_eq!(
+      highlight_snippet(&snippet),
+      "&lt;img src=x onerror=&quot;alert(&#39;&amp;&#39;)&quot;&gt; <mark>hit</mark>",
+    );
+  }
+}
@@ -0,0 +1,245 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{entities::room, msg::{MsgContent, Msg}, channel::ChannelEvent, presence::{Presence, Availability}, role::Role};
+
+#[derive(Debug, Deserialize)]
+pub struct AuthEvent {
+  pub token: String,
+  /// The last message the client has seen, to replay the ones it missed.
+  pub last_seen: Option<Uuid>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MsgEvent {
+  pub uuid: Uuid,
+  pub room: i32,
+  pub data: MsgContent,
+  pub reply_to: Option<Uuid>,
+  pub thread_root: Option<Uuid>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EditEvent {
+  pub uuid: Uuid,
+  pub data: MsgContent,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeleteEvent {
+  pub uuid: Uuid,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReactEvent {
+  pub uuid: Uuid,
+  /// A unicode emoji or a shortcode like `:tada:`.
+  pub emoji: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TypingEvent {
+  pub room: i32,
+  pub active: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReadEvent {
+  /// The last message read, in the room it was sent to.
+  pub uuid: Uuid,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type")]
+pub enum WsEvent {
+  Auth(AuthEvent),
+  Msg(MsgEvent),
+  Edit(EditEvent),
+  Delete(DeleteEvent),
+  React(ReactEvent),
+  Unreact(ReactEvent),
+  Typing(TypingEvent),
+  Read(ReadEvent),
+}
+
+/// A frame sent by the client.
+#[derive(Debug, Deserialize)]
+pub struct ClientFrame {
+  /// Echoed back in the acknowledgement or error caused by this frame.
+  pub request: Option<String>,
+  #[serde(flatten)]
+  pub event: WsEvent,
+}
+
+#[derive(Deserialize)]
+struct RequestOnly {
+  request: Option<String>,
+}
+
+impl ClientFrame {
+  /// Parses a text frame, keeping the request id for the error if the frame is invalid.
+  pub fn parse(text: &str) -> Result<Self, (serde_json::Error, Option<String>)> {
+    serde_json::from_str(text).map_err(|err| {
+      let request = serde_json::from_str::<RequestOnly>(text)
+        .ok()
+        .and_then(|frame| frame.request);
+
+      (err, request)
+    })
+  }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum ErrorCode {
+  InvalidFrame = 1,
+  Unauthenticated = 2,
+  Forbidden = 3,
+  NotFound = 4,
+  Conflict = 5,
+  Internal = 6,
+}
+
+/// A frame sent by the server.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type")]
+pub enum ServerEvent {
+  Auth {
+    code: i32,
+    msg: String,
+  },
+  Recv {
+    data: Msg,
+  },
+  Edit {
+    data: Msg,
+  },
+  Delete {
+    data: Msg,
+  },
+  /// Sent to the mentioned user, whichever room they are looking at.
+  Mentioned {
+    data: Msg,
+  },
+  Reaction {
+    user: i32,
+    room: i32,
+    uuid: Uuid,
+    emoji: String,
+    active: bool,
+  },
+  Typing {
+    user: i32,
+    room: i32,
+    active: bool,
+  },
+  Read {
+    user: i32,
+    room: i32,
+    uuid: Uuid,
+  },
+  RoleChanged {
+    room: i32,
+    user: i32,
+    role: Role,
+  },
+  /// A member left the room or was kicked out of it; sent to them as well.
+  MemberRemoved {
+    room: i32,
+    user: i32,
+    by: Option<i32>,
+    banned: bool,
+  },
+  RoomUpdated {
+    data: room::Model,
+  },
+  /// The room has been archived and will send nothing more.
+  RoomArchived {
+    room: i32,
+  },
+  PresenceChanged {
+    user: i32,
+    presence: Presence,
+    availability: Availability,
+  },
+  Ack {
+    uuid: Uuid,
+    sent: DateTime<Local>,
+    replay: bool,
+    request: Option<String>,
+  },
+  Resync {
+    /// The last message delivered in each room, to fetch the missed ones after.
+    cursors: HashMap<i32, Uuid>,
+  },
+  Error {
+    code: i32,
+    msg: String,
+    request: Option<String>,
+  },
+}
+
+impl ServerEvent {
+  pub fn error(code: ErrorCode, msg: String, request: Option<String>) -> Self {
+    Self::Error { code: code as i32, msg, request }
+  }
+
+  /// Converts an event for a connection into its frame; `None` for internal events.
+  pub fn from_channel(event: ChannelEvent) -> Option<Self> {
+    match event {
+      ChannelEvent::Msg(msg_event) => Some(Self::Recv { data: msg_event.msg }),
+      ChannelEvent::Edit(edit_event) => Some(Self::Edit { data: edit_event.msg }),
+      ChannelEvent::Delete(delete_event) => Some(Self::Delete { data: delete_event.msg }),
+      ChannelEvent::Mention(mention_event) => Some(Self::Mentioned { data: mention_event.msg }),
+      ChannelEvent::Reaction(reaction_event) => Some(Self::Reaction {
+        user: reaction_event.user,
+        room: reaction_event.room,
+        uuid: reaction_event.uuid,
+        emoji: reaction_event.emoji,
+        active: reaction_event.active,
+      }),
+      ChannelEvent::Typing(typing_event) => Some(Self::Typing {
+        user: typing_event.user,
+        room: typing_event.room,
+        active: typing_event.active,
+      }),
+      ChannelEvent::Read(read_event) => Some(Self::Read {
+        user: read_event.user,
+        room: read_event.room,
+        uuid: read_event.uuid,
+      }),
+      ChannelEvent::Role(role_event) => Some(Self::RoleChanged {
+        room: role_event.room,
+        user: role_event.user,
+        role: role_event.role,
+      }),
+      ChannelEvent::Removed(removed_event) => Some(Self::MemberRemoved {
+        room: removed_event.room,
+        user: removed_event.user,
+        by: removed_event.by,
+        banned: removed_event.banned,
+      }),
+      ChannelEvent::Room(room_event) => Some(Self::RoomUpdated { data: room_event.room }),
+      ChannelEvent::Archived(archived_event) => Some(Self::RoomArchived { room: archived_event.room }),
+      ChannelEvent::Presence(presence_event) => Some(Self::PresenceChanged {
+        user: presence_event.user,
+        presence: presence_event.presence,
+        availability: presence_event.availability,
+      }),
+      ChannelEvent::Ack(ack_event) => Some(Self::Ack {
+        uuid: ack_event.uuid,
+        sent: ack_event.sent,
+        replay: ack_event.replay,
+        request: ack_event.request,
+      }),
+      ChannelEvent::Error(error_event) => Some(Self::Error {
+        code: error_event.code,
+        msg: error_event.msg,
+        request: error_event.request,
+      }),
+      ChannelEvent::Lagged => None,
+    }
+  }
+}
@@ -0,0 +1,431 @@
+use std::{sync::Arc, time::Duration};
+
+use chrono::Local;
+use uuid::Uuid;
+
+use crate::{
+  AppState,
+  utils::{user_in_room, can_delete_msg, save_msg, get_msg, edit_msg, delete_msg, mark_read, save_mentions, add_reaction, remove_reaction, get_attachment, can_access_attachment},
+  entities::user,
+  msg::{Msg, MsgContent, ReplyPreview},
+  channel::{ChannelEvent, ConnId},
+};
+
+use super::event::{MsgEvent, EditEvent, DeleteEvent, ReactEvent, TypingEvent, ReadEvent, ErrorCode};
+
+/// Why an event from the client could not be handled, reported back to it.
+pub struct WsError {
+  pub code: ErrorCode,
+  pub msg: String,
+}
+
+impl WsError {
+  pub fn new(code: ErrorCode, msg: String) -> Self {
+    Self { code, msg }
+  }
+
+  fn internal(err: anyhow::Error) -> Self {
+    error!("[ws_in] {err}");
+    Self::new(ErrorCode::Internal, "Error accessing database!".to_string())
+  }
+}
+
+type Result<T> = std::result::Result<T, WsError>;
+
+/// Rejects content that clients may not send.
+fn check_content(data: &MsgContent) -> Result<()> {
+  match data {
+    MsgContent::System(_) => Err(WsError::new(
+      ErrorCode::Forbidden,
+      "System messages can only be posted by the server!".to_string(),
+    )),
+    MsgContent::Location(location) if !(-90.0..=90.0).contains(&location.latitude)
+      || !(-180.0..=180.0).contains(&location.longitude) => Err(WsError::new(
+      ErrorCode::InvalidFrame,
+      "The location is out of range!".to_string(),
+    )),
+    _ => Ok(()),
+  }
+}
+
+/// Fills in the details of the uploaded file `data` refers to, if any.
+async fn fill_attachment(
+  user: &user::Model,
+  state: &AppState,
+  mut data: MsgContent,
+) -> Result<MsgContent> {
+  let Some(id) = data.attachment() else {
+    return Ok(data);
+  };
+
+  let Some(attachment) = get_attachment(&state.db, id).await.map_err(WsError::internal)? else {
+    return Err(WsError::new(
+      ErrorCode::NotFound,
+      format!("The attachment `{id}` does not exist!"),
+    ));
+  };
+
+  if !can_access_attachment(&state.db, user.id, &attachment).await.map_err(WsError::internal)? {
+    return Err(WsError::new(
+      ErrorCode::Forbidden,
+      format!("You cannot access the attachment `{id}`!"),
+    ));
+  }
+
+  match &mut data {
+    MsgContent::File(file) => {
+      file.name = attachment.name;
+      file.mime = attachment.mime;
+      file.size = attachment.size as u64;
+    },
+    MsgContent::Image(image) => {
+      let (Some(width), Some(height)) = (attachment.width, attachment.height) else {
+        return Err(WsError::new(
+          ErrorCode::InvalidFrame,
+          format!("The attachment `{id}` is not an image!"),
+        ));
+      };
+
+      image.name = attachment.name;
+      image.mime = attachment.mime;
+      image.size = attachment.size as u64;
+      image.width = width as u32;
+      image.height = height as u32;
+      image.thumbnail = format!("/uploads/{id}/thumbnail");
+    },
+    _ => (),
+  }
+
+  Ok(data)
+}
+
+pub async fn handle_msg(
+  conn: ConnId,
+  user: &user::Model,
+  state: &AppState,
+  msg: MsgEvent,
+  request: Option<String>,
+) -> Result<()> {
+  if !user_in_room(&state.db, user.id, msg.room).await.map_err(WsError::internal)? {
+    return Err(WsError::new(
+      ErrorCode::Forbidden,
+      format!("You are not in the room `{}`!", msg.room),
+    ));
+  }
+
+  // A retry of a saved message gets its ack again, whatever has changed since.
+  match get_msg(&state.db, msg.uuid).await.map_err(WsError::internal)? {
+    Some(saved) if saved.sender == user.id => {
+      state.registry.lock().unwrap()
+        .send_to_conn(conn, ChannelEvent::new_ack(&saved, true, request));
+      return Ok(());
+    },
+    Some(_) => return Err(WsError::new(
+      ErrorCode::Conflict,
+      format!("The message uuid `{}` has been used!", msg.uuid),
+    )),
+    None => (),
+  }
+
+  check_content(&msg.data)?;
+
+  let data = fill_attachment(user, state, msg.data).await?;
+
+  let thread_root = match msg.thread_root {
+    None => None,
+    Some(uuid) => {
+      let root = get_msg_in_room(state, msg.room, uuid).await?;
+
+      if root.thread_root.is_some() {
+        return Err(WsError::new(
+          ErrorCode::InvalidFrame,
+          format!("The message `{uuid}` is in a thread itself!"),
+        ));
+      }
+
+      Some(uuid)
+    },
+  };
+
+  let reply_preview = match msg.reply_to {
+    None => None,
+    Some(uuid) => {
+      let replied = get_msg_in_room(state, msg.room, uuid).await?;
+
+      if replied.thread_root != thread_root && Some(replied.uuid) != thread_root {
+        return Err(WsError::new(
+          ErrorCode::InvalidFrame,
+          format!("The message `{uuid}` is not in the same thread!"),
+        ));
+      }
+
+      Some(ReplyPreview {
+        uuid,
+        sender: replied.sender,
+        preview: replied.data.map(|data| data.preview()),
+      })
+    },
+  };
+
+  let msg = Msg {
+    uuid: msg.uuid,
+    sender: user.id,
+    room: msg.room,
+    data: Some(data),
+    sent: Local::now(),
+    modified: false,
+    edited: None,
+    deleted: None,
+    reply_to: msg.reply_to,
+    thread_root,
+    reactions: vec![],
+    reply_preview,
+    thread_replies: 0,
+  };
+
+  let uuid = msg.uuid;
+
+  let Some((msg, new)) = save_msg(&state.db, msg).await.map_err(WsError::internal)? else {
+    return Err(WsError::new(
+      ErrorCode::Conflict,
+      format!("The message uuid `{uuid}` has been used!"),
+    ));
+  };
+
+  let mentioned = if new { notable_mentions(state, &msg).await } else { vec![] };
+
+  let mut registry = state.registry.lock().unwrap();
+
+  registry.send_to_conn(conn, ChannelEvent::new_ack(&msg, !new, request));
+
+  if new {
+    for user in mentioned {
+      registry.send_to_user(user, ChannelEvent::new_mention(msg.clone()));
+    }
+
+    registry.send_to_room(msg.room, ChannelEvent::new_msg(msg));
+  }
+
+  Ok(())
+}
+
+/// Records the mentions in `msg`, returning the users newly mentioned. The
+/// message stands even if this fails, so errors are only logged.
+async fn notable_mentions(state: &AppState, msg: &Msg) -> Vec<i32> {
+  save_mentions(&state.db, msg).await.unwrap_or_else(|err| {
+    error!("[ws_in] Failed to save the mentions in `{}`: {err}", msg.uuid);
+    vec![]
+  })
+}
+
+/// Gets a message referenced by a new message in `room`.
+async fn get_msg_in_room(state: &AppState, room: i32, uuid: Uuid) -> Result<Msg> {
+  match get_msg(&state.db, uuid).await.map_err(WsError::internal)? {
+    Some(msg) if msg.room == room => Ok(msg),
+    _ => Err(WsError::new(
+      ErrorCode::NotFound,
+      format!("The message `{uuid}` is not in the room `{room}`!"),
+    )),
+  }
+}
+
+pub async fn handle_edit(
+  user: &user::Model,
+  state: &AppState,
+  edit: EditEvent,
+) -> Result<()> {
+  check_content(&edit.data)?;
+
+  let msg = match get_msg(&state.db, edit.uuid).await.map_err(WsError::internal)? {
+    Some(msg) if msg.sender == user.id
+      && msg.deleted.is_none()
+      && !matches!(msg.data, Some(MsgContent::System(_))) => msg,
+    Some(_) => return Err(WsError::new(
+      ErrorCode::Forbidden,
+      format!("You cannot edit the message `{}`!", edit.uuid),
+    )),
+    None => return Err(WsError::new(
+      ErrorCode::NotFound,
+      format!("The message `{}` does not exist!", edit.uuid),
+    )),
+  };
+
+  // Those kicked out, or in an archived room, have no say in it any more.
+  if !user_in_room(&state.db, user.id, msg.room).await.map_err(WsError::internal)? {
+    return Err(WsError::new(
+      ErrorCode::Forbidden,
+      format!("You are not in the room `{}`!", msg.room),
+    ));
+  }
+
+  let data = fill_attachment(user, state, edit.data).await?;
+
+  let msg = edit_msg(&state.db, msg, data).await.map_err(WsError::internal)?;
+
+  let mentioned = notable_mentions(state, &msg).await;
+
+  let mut registry = state.registry.lock().unwrap();
+
+  for user in mentioned {
+    registry.send_to_user(user, ChannelEvent::new_mention(msg.clone()));
+  }
+
+  registry.send_to_room(msg.room, ChannelEvent::new_edit(msg));
+
+  Ok(())
+}
+
+pub async fn handle_delete(
+  user: &user::Model,
+  state: &AppState,
+  delete: DeleteEvent,
+) -> Result<()> {
+  let msg = match get_msg(&state.db, delete.uuid).await.map_err(WsError::internal)? {
+    Some(msg) if msg.deleted.is_none() => msg,
+    Some(_) => return Err(WsError::new(
+      ErrorCode::Forbidden,
+      format!("You cannot delete the message `{}`!", delete.uuid),
+    )),
+    None => return Err(WsError::new(
+      ErrorCode::NotFound,
+      format!("The message `{}` does not exist!", delete.uuid),
+    )),
+  };
+
+  if !can_delete_msg(&state.db, user.id, &msg).await.map_err(WsError::internal)? {
+    return Err(WsError::new(
+      ErrorCode::Forbidden,
+      format!("You cannot delete the message `{}`!", delete.uuid),
+    ));
+  }
+
+  let msg = delete_msg(&state.db, msg).await.map_err(WsError::internal)?;
+
+  state.registry.lock().unwrap()
+    .send_to_room(msg.room, ChannelEvent::new_delete(msg));
+
+  Ok(())
+}
+
+/// The longest emoji or shortcode accepted as a reaction, in bytes.
+const MAX_EMOJI_LEN: usize = 64;
+
+/// Adds or removes (`active: false`) a reaction of `user`.
+pub async fn handle_reaction(
+  user: &user::Model,
+  state: &AppState,
+  react: ReactEvent,
+  active: bool,
+) -> Result<()> {
+  let emoji = react.emoji;
+
+  if emoji.is_empty() || emoji.len() > MAX_EMOJI_LEN || emoji.contains(char::is_whitespace) {
+    return Err(WsError::new(
+      ErrorCode::InvalidFrame,
+      format!("`{emoji}` is not a valid reaction!"),
+    ));
+  }
+
+  let msg = match get_msg(&state.db, react.uuid).await.map_err(WsError::internal)? {
+    Some(msg) if msg.deleted.is_none() => msg,
+    Some(_) => return Err(WsError::new(
+      ErrorCode::Forbidden,
+      format!("The message `{}` has been deleted!", react.uuid),
+    )),
+    None => return Err(WsError::new(
+      ErrorCode::NotFound,
+      format!("The message `{}` does not exist!", react.uuid),
+    )),
+  };
+
+  if !user_in_room(&state.db, user.id, msg.room).await.map_err(WsError::internal)? {
+    return Err(WsError::new(
+      ErrorCode::Forbidden,
+      format!("You are not in the room `{}`!", msg.room),
+    ));
+  }
+
+  let changed = if active {
+    add_reaction(&state.db, user.id, &msg, &emoji).await
+  } else {
+    remove_reaction(&state.db, user.id, &msg, &emoji).await
+  };
+
+  if changed.map_err(WsError::internal)? {
+    state.registry.lock().unwrap()
+      .send_to_room(msg.room, ChannelEvent::new_reaction(user.id, &msg, emoji, active));
+  }
+
+  Ok(())
+}
+
+pub async fn handle_read(
+  user: &user::Model,
+  state: &AppState,
+  read: ReadEvent,
+) -> Result<()> {
+  let Some(msg) = get_msg(&state.db, read.uuid).await.map_err(WsError::internal)? else {
+    return Err(WsError::new(
+      ErrorCode::NotFound,
+      format!("The message `{}` does not exist!", read.uuid),
+    ));
+  };
+
+  if !user_in_room(&state.db, user.id, msg.room).await.map_err(WsError::internal)? {
+    return Err(WsError::new(
+      ErrorCode::Forbidden,
+      format!("You are not in the room `{}`!", msg.room),
+    ));
+  }
+
+  if mark_read(&state.db, user.id, &msg).await.map_err(WsError::internal)? {
+    state.registry.lock().unwrap()
+      .send_to_room(msg.room, ChannelEvent::new_read(user.id, &msg));
+  }
+
+  Ok(())
+}
+
+/// How long someone is shown as typing without sending another update.
+const TYPING_TIMEOUT: Duration = Duration::from_secs(6);
+
+pub fn handle_typing(
+  user: &user::Model,
+  state: &Arc<AppState>,
+  typing: TypingEvent,
+) -> Result<()> {
+  let mut registry = state.registry.lock().unwrap();
+
+  if !registry.in_room(user.id, typing.room) {
+    return Err(WsError::new(
+      ErrorCode::Forbidden,
+      format!("You are not in the room `{}`!", typing.room),
+    ));
+  }
+
+  if !typing.active {
+    if registry.stop_typing(user.id, typing.room, None) {
+      registry.send_to_room_except(typing.room, Some(user.id), ChannelEvent::new_typing(user.id, typing.room, false));
+    }
+
+    return Ok(());
+  }
+
+  let update = registry.start_typing(user.id, typing.room);
+
+  registry.send_to_room_except(typing.room, Some(user.id), ChannelEvent::new_typing(user.id, typing.room, true));
+
+  let (user, room, state) = (user.id, typing.room, state.clone());
+
+  tokio::spawn(async move {
+    tokio::time::sleep(TYPING_TIMEOUT).await;
+
+    let mut registry = state.registry.lock().unwrap();
+
+    if registry.stop_typing(user, room, Some(update)) {
+      registry.send_to_room_except(room, Some(user), ChannelEvent::new_typing(user, room, false));
+    }
+  });
+
+  Ok(())
+}
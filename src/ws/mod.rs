@@ -0,0 +1,408 @@
+mod event;
+mod handler;
+
+use std::{sync::{Arc, Mutex}, collections::{HashMap, HashSet}, time::{Duration, Instant}};
+
+use futures::{stream::{StreamExt, SplitSink, SplitStream}, SinkExt};
+use tokio::sync::mpsc;
+use axum::{extract::{ws::{WebSocketUpgrade, WebSocket, Message}, State}, response::Response};
+use uuid::Uuid;
+
+use crate::{AppState, utils::{auth, get_msg, get_user_rooms, get_msgs_since}, entities::user, msg::Msg, channel::{ChannelEvent, ConnId}, presence::Availability};
+
+use self::{event::{WsEvent, ClientFrame, ServerEvent, ErrorCode}, handler::{WsError, handle_msg, handle_edit, handle_delete, handle_reaction, handle_typing, handle_read}};
+
+pub async fn ws(
+  State(state): State<Arc<AppState>>,
+  ws: WebSocketUpgrade,
+) -> Response {
+  ws.on_upgrade(|socket| handle_ws(state, socket))
+}
+
+async fn handle_ws(
+  state: Arc<AppState>,
+  socket: WebSocket,
+) {
+  info!("[ws] New WebSocket connection...");
+
+  let (mut ws_out, mut ws_in) = socket.split();
+
+  let authenticated = tokio::time::timeout(
+    state.config.auth_timeout,
+    authenticate(&state, &mut ws_out, &mut ws_in),
+  ).await;
+
+  let (user, last_seen) = match authenticated {
+    Ok(Some(authenticated)) => authenticated,
+    Ok(None) => {
+      info!("[ws] WebSocket connection closed without authenticating!");
+      return;
+    },
+    Err(_) => {
+      info!("[ws] WebSocket connection did not authenticate in time!");
+      let _ = ws_out.close().await;
+      return;
+    },
+  };
+
+  let availability = Availability::from_i32(user.availability);
+  let (conn, receiver) = state.registry.lock().unwrap().connect(user.id, availability);
+
+  // Rooms joined after `connect` are picked up by the registry itself,
+  // and rooms left since are skipped by `join_loaded`.
+  let rooms = match get_user_rooms(&state.db, user.id).await {
+    Ok(rooms) => rooms,
+    Err(err) => {
+      error!("[ws] {err}");
+      state.registry.lock().unwrap().disconnect(conn);
+      return;
+    },
+  };
+
+  let rooms = {
+    let mut registry = state.registry.lock().unwrap();
+
+    let rooms = registry.join_loaded(conn, user.id, rooms);
+    registry.refresh_presence(user.id, state.config.idle_after);
+
+    rooms
+  };
+
+  // Loaded after joining the rooms, so nothing falls in between the replay and live delivery.
+  let replay = match last_seen {
+    Some(last_seen) => load_replay(&state, rooms, last_seen).await,
+    None => Replay::default(),
+  };
+
+  // Any frame from the client, pongs included, shows that the connection is alive.
+  let last_heard = Arc::new(Mutex::new(Instant::now()));
+
+  let mut write_task = tokio::spawn(
+    write(
+      conn,
+      state.clone(),
+      replay,
+      last_heard.clone(),
+      receiver,
+      ws_out,
+    )
+  );
+
+  let mut read_task = tokio::spawn(
+    read(
+      conn,
+      user,
+      state.clone(),
+      last_heard,
+      ws_in,
+    )
+  );
+
+  // Either side stopping means the connection is gone.
+  tokio::select! {
+    _ = &mut write_task => read_task.abort(),
+    _ = &mut read_task => write_task.abort(),
+  }
+
+  state.registry.lock().unwrap().disconnect(conn);
+  info!("[ws] Authenticated WebSocket connection closed!");
+}
+
+/// Waits for the client to authenticate, returning the user and where to resume from.
+async fn authenticate(
+  state: &AppState,
+  ws_out: &mut SplitSink<WebSocket, Message>,
+  ws_in: &mut SplitStream<WebSocket>,
+) -> Option<(user::Model, Option<Uuid>)> {
+  while let Some(msg) = ws_in.next().await {
+    let text = match msg {
+      Ok(Message::Text(text)) => text,
+      Ok(Message::Binary(_)) => {
+        let frame = ServerEvent::error(ErrorCode::InvalidFrame, "Binary frames are not supported!".to_string(), None);
+
+        if !send_frame(ws_out, &frame).await {
+          return None;
+        }
+
+        continue;
+      },
+      Ok(Message::Close(_)) => return None,
+      Ok(_) => continue,
+      Err(err) => {
+        error!("[ws] {err}");
+        return None;
+      },
+    };
+
+    let frame = match ClientFrame::parse(&text) {
+      Ok(frame) => frame,
+      Err((err, request)) => {
+        error!("[ws] {err}");
+
+        if !send_frame(ws_out, &ServerEvent::error(ErrorCode::InvalidFrame, err.to_string(), request)).await {
+          return None;
+        }
+
+        continue;
+      },
+    };
+
+    let WsEvent::Auth(auth_msg) = frame.event else {
+      let frame = ServerEvent::error(ErrorCode::Unauthenticated, "Please authenticate first!".to_string(), frame.request);
+
+      if !send_frame(ws_out, &frame).await {
+        return None;
+      }
+
+      continue;
+    };
+
+    info!("[ws] Authenticating WebSocket connection...");
+
+    let user = match auth(&state.db, &auth_msg.token).await {
+      Ok(user) => user,
+      Err(err) => {
+        error!("[ws] {err}");
+
+        if !send_frame(ws_out, &ServerEvent::Auth { code: 1, msg: err.to_string() }).await {
+          return None;
+        }
+
+        continue;
+      },
+    };
+
+    if !send_frame(ws_out, &ServerEvent::Auth { code: 0, msg: String::new() }).await {
+      return None;
+    }
+
+    info!("[ws] WebSocket connection authenticated!");
+
+    return Some((user, auth_msg.last_seen));
+  }
+
+  None
+}
+
+/// Messages a reconnecting client missed while it was offline.
+#[derive(Default)]
+struct Replay {
+  msgs: Vec<Msg>,
+  /// Whether the client still has to fetch more of the history itself.
+  incomplete: bool,
+}
+
+/// How many missed messages are replayed at most before asking the client to resync.
+const REPLAY_LIMIT: u64 = 500;
+
+async fn load_replay(
+  state: &AppState,
+  rooms: Vec<i32>,
+  last_seen: Uuid,
+) -> Replay {
+  let since = match get_msg(&state.db, last_seen).await {
+    Ok(Some(since)) => since,
+    Ok(None) => {
+      info!("[ws] The last seen message `{last_seen}` does not exist!");
+      return Replay { msgs: vec![], incomplete: true };
+    },
+    Err(err) => {
+      error!("[ws] {err}");
+      return Replay { msgs: vec![], incomplete: true };
+    },
+  };
+
+  match get_msgs_since(&state.db, rooms, &since, REPLAY_LIMIT).await {
+    Ok((msgs, incomplete)) => Replay { msgs, incomplete },
+    Err(err) => {
+      error!("[ws] {err}");
+      Replay { msgs: vec![], incomplete: true }
+    },
+  }
+}
+
+async fn read(
+  conn: ConnId,
+  user: user::Model,
+  state: Arc<AppState>,
+  last_heard: Arc<Mutex<Instant>>,
+  mut ws_in: SplitStream<WebSocket>,
+) {
+  while let Some(msg) = ws_in.next().await {
+    *last_heard.lock().unwrap() = Instant::now();
+
+    let text = match msg {
+      Ok(Message::Text(text)) => text,
+      Ok(Message::Binary(_)) => {
+        state.registry.lock().unwrap().send_to_conn(conn, ChannelEvent::new_error(
+          ErrorCode::InvalidFrame as i32,
+          "Binary frames are not supported!".to_string(),
+          None,
+        ));
+        continue;
+      },
+      Ok(Message::Close(_)) => break,
+      Ok(_) => continue,
+      Err(err) => {
+        error!("[ws_in] {err}");
+        break;
+      },
+    };
+
+    let frame = match ClientFrame::parse(&text) {
+      Ok(frame) => frame,
+      Err((err, request)) => {
+        error!("[ws_in] {err}");
+        state.registry.lock().unwrap().send_to_conn(conn, ChannelEvent::new_error(
+          ErrorCode::InvalidFrame as i32,
+          err.to_string(),
+          request,
+        ));
+        continue;
+      },
+    };
+
+    info!("[ws_in] Received message: {:?}", frame);
+
+    {
+      let mut registry = state.registry.lock().unwrap();
+
+      registry.touch(conn);
+      registry.refresh_presence(user.id, state.config.idle_after);
+    }
+
+    let request = frame.request;
+
+    let result = match frame.event {
+      WsEvent::Msg(msg) => handle_msg(conn, &user, &state, msg, request.clone()).await,
+      WsEvent::Edit(edit) => handle_edit(&user, &state, edit).await,
+      WsEvent::Delete(delete) => handle_delete(&user, &state, delete).await,
+      WsEvent::React(react) => handle_reaction(&user, &state, react, true).await,
+      WsEvent::Unreact(react) => handle_reaction(&user, &state, react, false).await,
+      WsEvent::Typing(typing) => handle_typing(&user, &state, typing),
+      WsEvent::Read(read) => handle_read(&user, &state, read).await,
+      WsEvent::Auth(_) => Err(WsError::new(ErrorCode::Conflict, "The connection is already authenticated!".to_string())),
+    };
+
+    if let Err(err) = result {
+      info!("[ws_in] {}", err.msg);
+      state.registry.lock().unwrap().send_to_conn(conn, ChannelEvent::new_error(
+        err.code as i32,
+        err.msg,
+        request,
+      ));
+    }
+  }
+}
+
+/// How long sending a single frame may take before the client is considered gone.
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+async fn send_frame(
+  ws_out: &mut SplitSink<WebSocket, Message>,
+  frame: &ServerEvent,
+) -> bool {
+  match serde_json::to_string(frame) {
+    Ok(text) => send_message(ws_out, Message::Text(text)).await,
+    Err(err) => {
+      error!("[ws_out] {err}");
+      true
+    },
+  }
+}
+
+async fn send_message(
+  ws_out: &mut SplitSink<WebSocket, Message>,
+  msg: Message,
+) -> bool {
+  match tokio::time::timeout(SEND_TIMEOUT, ws_out.send(msg)).await {
+    Ok(Ok(_)) => true,
+    Ok(Err(err)) => {
+      error!("[ws_out] {err}");
+      false
+    },
+    Err(_) => {
+      error!("[ws_out] Timed out sending a frame!");
+      false
+    },
+  }
+}
+
+async fn write(
+  conn: ConnId,
+  state: Arc<AppState>,
+  replay: Replay,
+  last_heard: Arc<Mutex<Instant>>,
+  mut receiver: mpsc::Receiver<ChannelEvent>,
+  mut ws_out: SplitSink<WebSocket, Message>,
+) {
+  let mut cursors = HashMap::new();
+
+  // Messages sent while the replay was loading may be queued as well.
+  let mut replayed = HashSet::new();
+
+  for msg in replay.msgs {
+    cursors.insert(msg.room, msg.uuid);
+    replayed.insert(msg.uuid);
+
+    if !send_frame(&mut ws_out, &ServerEvent::Recv { data: msg }).await {
+      return;
+    }
+  }
+
+  if replay.incomplete && !send_frame(&mut ws_out, &ServerEvent::Resync { cursors: cursors.clone() }).await {
+    return;
+  }
+
+  let mut ping = tokio::time::interval(state.config.ping_interval);
+  ping.tick().await; // The first tick completes immediately
+
+  loop {
+    let event = tokio::select! {
+      event = receiver.recv() => match event {
+        Some(event) => event,
+        None => break,
+      },
+      _ = ping.tick() => {
+        let silent = last_heard.lock().unwrap().elapsed();
+
+        if silent > state.config.ping_interval + state.config.ping_timeout {
+          info!("[ws_out] Connection `{conn}` timed out!");
+          break;
+        }
+
+        if !send_message(&mut ws_out, Message::Ping(vec![])).await {
+          break;
+        }
+
+        continue;
+      },
+    };
+
+    let frame = match event {
+      ChannelEvent::Msg(msg_event) if replayed.remove(&msg_event.msg.uuid) => {
+        continue;
+      },
+      ChannelEvent::Msg(ref msg_event) => {
+        cursors.insert(msg_event.msg.room, msg_event.msg.uuid);
+        ServerEvent::from_channel(event)
+      },
+      ChannelEvent::Lagged => {
+        warn!("[ws_out] Connection `{conn}` lagged behind!");
+        state.registry.lock().unwrap().resync(conn);
+        Some(ServerEvent::Resync { cursors: cursors.clone() })
+      },
+      event => ServerEvent::from_channel(event),
+    };
+
+    let Some(frame) = frame else {
+      continue;
+    };
+
+    if !send_frame(&mut ws_out, &frame).await {
+      break;
+    }
+  }
+
+  let _ = ws_out.close().await;
+}